@@ -15,15 +15,76 @@ use crate::utils::misc::calc_hash;
 // Local: Token, depth
 
 // chunk being written to (tied to Function) + locals which have tokens<'src>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionType {
+    Script, // top level code
+    Function
+}
+
+/// Variable captured by a closure: either a local slot of the enclosing function (is_local)
+/// or one of the enclosing function's own upvalues
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpvalueRef {
+    pub index:usize,
+    pub is_local:bool
+}
+
 #[derive(Debug)]
 pub struct Compiler {
     locals:Vec<Local>,
-    curr_depth:usize
+    upvalues:Vec<UpvalueRef>,
+    curr_depth:usize,
+    temps:usize, // values of an unfinished expression above the locals e.g the left operand of + while parsing the right
+    function_type:FunctionType,
+    enclosing:Option<Box<Compiler>> // compiler of the function this one is nested in
 }   
 
 impl<'src> Compiler {
     pub fn new()->Compiler {
-        Compiler { locals: Vec::with_capacity(STACK_SIZE), curr_depth: 0 }
+        Compiler { locals: Vec::with_capacity(STACK_SIZE), upvalues: vec![], curr_depth: 0, temps: 0, function_type: FunctionType::Script, enclosing: None }
+    }
+
+    /// Compiler for a function body nested in enclosing: slot 0 of the frame holds the function being called
+    pub fn new_function(enclosing:Compiler)->Compiler {
+        let mut locals=Vec::with_capacity(STACK_SIZE);
+        locals.push(Local::new("", 0, 0, false));
+        Compiler { locals, upvalues: vec![], curr_depth: 0, temps: 0, function_type: FunctionType::Function, enclosing: Some(Box::new(enclosing)) }
+    }
+
+    /// Finish a function: returns the enclosing compiler and the upvalues the function captures
+    pub fn end_function(self)->(Compiler, Vec<UpvalueRef>) {
+        let enclosing=self.enclosing.expect("Function compiler without enclosing compiler");
+        (*enclosing, self.upvalues)
+    }
+
+    pub fn function_type(&self)->FunctionType {
+        self.function_type
+    }
+
+    pub fn depth(&self)->usize {
+        self.curr_depth
+    }
+
+    /// Number of locals declared in scopes deeper than depth: what leaving them jumps over must pop
+    pub fn locals_deeper_than(&self, depth:usize)->usize {
+        self.locals.iter().rev().take_while(|loc| loc.depth > depth).count()
+    }
+
+    /// Stack height relative to the frame once the scopes deeper than depth are left: just above its last local
+    pub fn slots_at(&self, depth:usize)->usize {
+        self.locals.iter().rev()
+            .find(|loc| loc.depth <= depth)
+            .map(|loc| loc.slot+1)
+            .unwrap_or(0)
+    }
+
+    /// count values are pushed that locals declared before pop_temps must be placed above
+    pub fn push_temps(&mut self, count:usize) {
+        self.temps+=count;
+    }
+
+    pub fn pop_temps(&mut self, count:usize) {
+        self.temps-=count;
     }
 
     pub fn begin_scope(&mut self) {
@@ -32,7 +93,6 @@ impl<'src> Compiler {
 
     pub fn end_scope(&mut self)->usize {
         if !self.is_local() {
-            assert!(self.locals.is_empty());
             return 0;
         }
         
@@ -44,7 +104,7 @@ impl<'src> Compiler {
         loop {
             match self.locals.last() {
                 Some(loc) => {
-                    if loc.depth==curr {
+                    if loc.depth<=curr {
                         break;
                     }
                     self.locals.pop();
@@ -65,21 +125,54 @@ impl<'src> Compiler {
 
     /// If local found, return corresponding index in value stack to resolve
     pub fn resolve_local(&self, token:&str)->Option<usize> {
-        let n=self.locals.len();
-        for (idx,loc) in self.locals.iter().rev().enumerate() {
-            if loc.is_equal_to(&token) {
-                return Some(n-1-idx)
-            }
+        self.locals.iter().rev()
+            .find(|loc| loc.is_equal_to(token))
+            .map(|loc| loc.slot)
+    }
+
+    /// Whether the local (or local of an enclosing function) called token was declared with let mut.
+    /// None if it isn't one i.e it is a global
+    pub fn is_mutable(&self, token:&str)->Option<bool> {
+        match self.locals.iter().rev().find(|loc| loc.is_equal_to(token)) {
+            Some(loc) => Some(loc.mutable),
+            None => self.enclosing.as_ref()?.is_mutable(token)
         }
-        None
     }
 
-    /// Only add local if curr scope is local. Return idx of local if it was added.
+    /// If token is a local of an enclosing function, return idx of the upvalue that captures it
+    pub fn resolve_upvalue(&mut self, token:&str)->Option<usize> {
+        let enclosing=self.enclosing.as_mut()?;
+
+        if let Some(idx) = enclosing.resolve_local(token) {
+            return Some(self.add_upvalue(UpvalueRef { index: idx, is_local: true }));
+        }
+
+        // captured further out: enclosing captures it first and we capture enclosing's upvalue
+        let idx=enclosing.resolve_upvalue(token)?;
+        Some(self.add_upvalue(UpvalueRef { index: idx, is_local: false }))
+    }
+
+    // reuse upvalue if the same variable is captured more than once
+    fn add_upvalue(&mut self, upvalue:UpvalueRef)->usize {
+        if let Some(idx) = self.upvalues.iter().position(|u| *u==upvalue) {
+            return idx;
+        }
+
+        self.upvalues.push(upvalue);
+        self.upvalues.len()-1
+    }
+
+    /// Only add local if curr scope is local. Return its slot in the value stack if it was added.
     pub fn add_local(&mut self, token:&str)->Option<usize>{
+        self.add_local_as(token, false)
+    }
+
+    /// add_local for a local that can be assigned to if mutable
+    pub fn add_local_as(&mut self, token:&str, mutable:bool)->Option<usize>{
         if self.is_local() {
-            let local=Local::new(token, self.curr_depth);
-            self.locals.push(local);
-            Some(self.locals.len()-1)
+            let slot=self.locals.len()+self.temps;
+            self.locals.push(Local::new(token, self.curr_depth, slot, mutable));
+            Some(slot)
         } else {
             None
         }
@@ -90,12 +183,14 @@ impl<'src> Compiler {
 #[derive(Debug)]
 pub struct Local {
     token_hash:u64, // hash identifier e.g hash("x")
-    depth:usize
+    depth:usize,
+    slot:usize, // idx in the frame's part of the value stack
+    mutable:bool // declared with let mut: can be assigned to
 }
 
 impl<'src> Local {
-    pub fn new(ident:&str, depth:usize)->Local {
-        Local { token_hash: calc_hash(ident), depth }
+    pub fn new(ident:&str, depth:usize, slot:usize, mutable:bool)->Local {
+        Local { token_hash: calc_hash(ident), depth, slot, mutable }
     }
 
     // compare hash to hash of input
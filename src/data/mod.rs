@@ -1,2 +1,3 @@
 pub mod ops;
-pub mod stack;
\ No newline at end of file
+pub mod stack;
+pub mod obj;
\ No newline at end of file
@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_traits::FromPrimitive;
+
+use crate::compiler::UpvalueRef;
+use crate::data::ops::{Chunk, IntType, Value};
+use crate::utils::err::*;
+
+// Objects that don't fit in the 16 byte Value live on the VM heap.
+// Value holds the index of the object in the heap so that it stays Copy e.g Value::ObjClosure(idx)
+
+/// Compiled function: each function owns the chunk its body was written to
+#[derive(Debug)]
+pub struct Function {
+    pub name:String,
+    pub arity:usize,
+    pub chunk:Chunk,
+    pub upvalues:Vec<UpvalueRef> // variables to capture when a closure is made from this function
+}
+
+impl Function {
+    pub fn new(name:&str, arity:usize, chunk:Chunk, upvalues:Vec<UpvalueRef>)->Function {
+        Function { name: name.to_string(), arity, chunk, upvalues }
+    }
+
+    /// Top level code is run as a function with no params
+    pub fn script(chunk:Chunk)->Function {
+        Function::new("script", 0, chunk, vec![])
+    }
+}
+
+/// Record type declared with type Name { fields }, or a variant Name(fields) of a sum type
+#[derive(Debug)]
+pub struct RecordType {
+    pub name:String,
+    pub fields:Vec<String>,
+    pub sum:Option<String> // name of the sum type for a variant
+}
+
+impl RecordType {
+    pub fn new(name:&str, fields:Vec<String>)->RecordType {
+        RecordType { name: name.to_string(), fields, sum: None }
+    }
+
+    pub fn variant(name:&str, fields:Vec<String>, sum:&str)->RecordType {
+        RecordType { name: name.to_string(), fields, sum: Some(sum.to_string()) }
+    }
+
+    /// Position of the field called name in the record's fields
+    pub fn field_idx(&self, name:&str)->Option<usize> {
+        self.fields.iter().position(|field| field==name)
+    }
+}
+
+/// Value of a record type: one value per field in the order the type declares them
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub ty:Rc<RecordType>,
+    pub fields:Vec<Value>
+}
+
+/// Function at runtime along with the variables it captured: idx of each Obj::Upvalue in the heap
+#[derive(Debug)]
+pub struct Closure {
+    pub function:Rc<Function>,
+    pub upvalues:Vec<usize>,
+    pub module:usize // idx in VM modules of the module it was made in: its globals are the ones the function sees
+}
+
+impl Closure {
+    pub fn new(function:Rc<Function>, upvalues:Vec<usize>, module:usize)->Closure {
+        Closure { function, upvalues, module }
+    }
+}
+
+/// Captured variable: Open while it still lives on the value stack (absolute slot),
+/// Closed holds the value once its scope has ended
+#[derive(Debug, Clone, Copy)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value)
+}
+
+#[derive(Debug)]
+pub enum Obj {
+    Closure(Rc<Closure>),
+    Upvalue(Upvalue),
+    Range(IntType, IntType), // start (inclusive), end (exclusive)
+    List(Vec<Value>),
+    Map(Map),
+    Tuple(Vec<Value>),
+    BigInt(BigInt),
+    Type(Rc<RecordType>),
+    Record(Record)
+}
+
+impl Obj {
+    /// Add the heap idx of every object this one refers to
+    fn children(&self, pending:&mut Vec<usize>) {
+        match self {
+            Obj::Closure(closure) => pending.extend(closure.upvalues.iter()),
+            Obj::Upvalue(Upvalue::Closed(value)) => pending.extend(value.heap_idx()),
+            Obj::List(items) | Obj::Tuple(items) => pending.extend(items.iter().filter_map(Value::heap_idx)),
+            Obj::Map(map) => {
+                for (key, value) in map.entries() {
+                    pending.extend(key.heap_idx());
+                    pending.extend(value.heap_idx());
+                }
+            },
+            Obj::Record(record) => pending.extend(record.fields.iter().filter_map(Value::heap_idx)),
+            Obj::Upvalue(Upvalue::Open(_)) | Obj::Range(..) | Obj::BigInt(_) | Obj::Type(_) => ()
+        }
+    }
+}
+
+/// What a map key's equality depends on, so that keys equal by == find the same entry e.g 1 and 1.0, or two
+/// tuples with the same items. Made by Heap::key, which rejects lists and maps as they can change once added
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Int(IntType), // ints and floats with no fractional part
+    BigInt(BigInt),
+    Float(u64), // bits of a float with a fractional part
+    Tuple(Vec<Key>),
+    Record(usize, Vec<Key>), // address of the record type, fields
+    Range(IntType, IntType),
+    Other(Value) // equal only to itself e.g strings, bools and functions
+}
+
+/// Map from any Value to Value that keeps its keys in insertion order
+#[derive(Debug, Default)]
+pub struct Map {
+    entries:Vec<(Value, Value)>,
+    index:HashMap<Key, usize> // key -> idx in entries
+}
+
+impl Map {
+    pub fn new()->Map {
+        Map { entries: vec![], index: HashMap::new() }
+    }
+
+    pub fn get(&self, key:&Key)->Option<Value> {
+        self.index.get(key).map(|idx| self.entries[*idx].1)
+    }
+
+    pub fn has(&self, key:&Key)->bool {
+        self.index.contains_key(key)
+    }
+
+    /// Set the value for the key made from key_value: an existing entry keeps the key it was added with
+    pub fn insert(&mut self, key:Key, key_value:Value, value:Value) {
+        match self.index.get(&key) {
+            Some(idx) => self.entries[*idx].1=value,
+            None => {
+                self.index.insert(key, self.entries.len());
+                self.entries.push((key_value, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key:&Key)->Option<Value> {
+        let idx=self.index.remove(key)?;
+        let (_, value)=self.entries.remove(idx);
+
+        // entries after idx moved back by one
+        for pos in self.index.values_mut() {
+            if *pos > idx {
+                *pos-=1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn len(&self)->usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self)->bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self)->&[(Value, Value)] {
+        &self.entries
+    }
+
+    pub fn keys(&self)->Vec<Value> {
+        self.entries.iter().map(|(key, _)| *key).collect()
+    }
+
+    pub fn values(&self)->Vec<Value> {
+        self.entries.iter().map(|(_, value)| *value).collect()
+    }
+}
+
+// a collection is only worth it once there are at least this many objects
+const GC_MIN:usize=1024;
+
+/// Objects referred to by heap idx: unreachable ones are freed by collect and their slots reused
+#[derive(Debug)]
+pub struct Heap {
+    objects:Vec<Option<Obj>>, // None: freed slot in free
+    free:Vec<usize>,
+    bigints:HashMap<BigInt, usize>, // big int -> its idx: interned so that equal big ints are the same Value
+    live:usize, // objects not freed
+    next_gc:usize // collect once this many objects are live
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new()->Heap {
+        Heap { objects: vec![], free: vec![], bigints: HashMap::new(), live: 0, next_gc: GC_MIN }
+    }
+
+    /// Returns index of the allocated object for use in Value: a freed slot is reused if there is one
+    pub fn alloc(&mut self, obj:Obj)->usize {
+        self.live+=1;
+
+        match self.free.pop() {
+            Some(idx) => {
+                self.objects[idx]=Some(obj);
+                idx
+            },
+            None => {
+                self.objects.push(Some(obj));
+                self.objects.len()-1
+            }
+        }
+    }
+
+    /// Whether enough objects were allocated since the last collection to collect again
+    pub fn should_collect(&self)->bool {
+        self.live >= self.next_gc
+    }
+
+    /// Number of objects that have not been freed
+    pub fn live(&self)->usize {
+        self.live
+    }
+
+    /// Free every object that can't be reached from roots or from root_objects, which are heap idx of objects
+    /// held outside of a Value e.g the upvalues of running closures
+    pub fn collect(&mut self, roots:Vec<Value>, root_objects:Vec<usize>) {
+        let mut marked=vec![false; self.objects.len()];
+        let mut pending=root_objects;
+        pending.extend(roots.iter().filter_map(Value::heap_idx));
+
+        while let Some(idx) = pending.pop() {
+            if marked[idx] {
+                continue;
+            }
+            marked[idx]=true;
+
+            if let Some(obj) = &self.objects[idx] {
+                obj.children(&mut pending);
+            }
+        }
+
+        for (idx, is_marked) in marked.iter().enumerate() {
+            if !is_marked && self.objects[idx].is_some() {
+                self.objects[idx]=None;
+                self.free.push(idx);
+                self.live-=1;
+            }
+        }
+
+        self.bigints.retain(|_, idx| marked[*idx]);
+        self.next_gc=(self.live*2).max(GC_MIN);
+    }
+
+    /// Returns index of the big int, reusing an existing one that is equal
+    pub fn alloc_bigint(&mut self, n:BigInt)->usize {
+        if let Some(idx) = self.bigints.get(&n) {
+            return *idx;
+        }
+
+        let idx=self.alloc(Obj::BigInt(n.clone()));
+        self.bigints.insert(n, idx);
+        idx
+    }
+
+    /// Key for value in a map: errors for lists and maps
+    pub fn key(&self, value:Value)->Result<Key> {
+        let key=match value {
+            Value::Number(n) => Key::Int(n),
+            Value::Float(f) if f.fract()==0.0 && f >= IntType::MIN as f64 && f < IntType::MAX as f64 => Key::Int(f as IntType),
+            Value::Float(f) if f.fract()==0.0 => match BigInt::from_f64(f) {
+                Some(n) => Key::BigInt(n),
+                None => Key::Float(f.to_bits())
+            },
+            Value::Float(f) => Key::Float(f.to_bits()),
+            Value::ObjBigInt(idx) => Key::BigInt(self.expect_bigint(idx)?.clone()),
+            Value::ObjTuple(idx) => {
+                let items=self.expect_tuple(idx)?.iter().map(|item| self.key(*item)).collect::<Result<_>>()?;
+                Key::Tuple(items)
+            },
+            Value::ObjRecord(idx) => {
+                let record=self.expect_record(idx)?;
+                let fields=record.fields.iter().map(|field| self.key(*field)).collect::<Result<_>>()?;
+                Key::Record(Rc::as_ptr(&record.ty) as usize, fields)
+            },
+            Value::ObjRange(idx) => {
+                let (start, end)=self.expect_range(idx)?;
+                Key::Range(start, end)
+            },
+            Value::ObjList(_) | Value::ObjMap(_) => {
+                let msg=format!("Cannot use a {} as a map key: it can change after being added", value.type_name());
+                return err_other!(msg);
+            },
+            _ => Key::Other(value)
+        };
+        Ok(key)
+    }
+
+    pub fn get(&self, idx:usize)->Option<&Obj> {
+        self.objects.get(idx).and_then(Option::as_ref)
+    }
+
+    fn get_mut(&mut self, idx:usize)->Option<&mut Obj> {
+        self.objects.get_mut(idx).and_then(Option::as_mut)
+    }
+
+    pub fn expect_closure(&self, idx:usize)->Result<Rc<Closure>> {
+        match self.get(idx) {
+            Some(Obj::Closure(closure)) => Ok(closure.clone()),
+            _ => err_other!("Expected function at heap index {}", idx)
+        }
+    }
+
+    pub fn expect_upvalue(&self, idx:usize)->Result<Upvalue> {
+        match self.get(idx) {
+            Some(Obj::Upvalue(upvalue)) => Ok(*upvalue),
+            _ => err_other!("Expected upvalue at heap index {}", idx)
+        }
+    }
+
+    pub fn expect_range(&self, idx:usize)->Result<(IntType, IntType)> {
+        match self.get(idx) {
+            Some(Obj::Range(start, end)) => Ok((*start, *end)),
+            _ => err_other!("Expected range at heap index {}", idx)
+        }
+    }
+
+    pub fn expect_list(&self, idx:usize)->Result<&Vec<Value>> {
+        match self.get(idx) {
+            Some(Obj::List(items)) => Ok(items),
+            _ => err_other!("Expected list at heap index {}", idx)
+        }
+    }
+
+    pub fn expect_list_mut(&mut self, idx:usize)->Result<&mut Vec<Value>> {
+        match self.get_mut(idx) {
+            Some(Obj::List(items)) => Ok(items),
+            _ => err_other!("Expected list at heap index {}", idx)
+        }
+    }
+
+    pub fn expect_tuple(&self, idx:usize)->Result<&Vec<Value>> {
+        match self.get(idx) {
+            Some(Obj::Tuple(items)) => Ok(items),
+            _ => err_other!("Expected tuple at heap index {}", idx)
+        }
+    }
+
+    pub fn expect_map(&self, idx:usize)->Result<&Map> {
+        match self.get(idx) {
+            Some(Obj::Map(map)) => Ok(map),
+            _ => err_other!("Expected map at heap index {}", idx)
+        }
+    }
+
+    pub fn expect_map_mut(&mut self, idx:usize)->Result<&mut Map> {
+        match self.get_mut(idx) {
+            Some(Obj::Map(map)) => Ok(map),
+            _ => err_other!("Expected map at heap index {}", idx)
+        }
+    }
+
+    pub fn expect_bigint(&self, idx:usize)->Result<&BigInt> {
+        match self.get(idx) {
+            Some(Obj::BigInt(n)) => Ok(n),
+            _ => err_other!("Expected big int at heap index {}", idx)
+        }
+    }
+
+    pub fn expect_type(&self, idx:usize)->Result<Rc<RecordType>> {
+        match self.get(idx) {
+            Some(Obj::Type(ty)) => Ok(ty.clone()),
+            _ => err_other!("Expected type at heap index {}", idx)
+        }
+    }
+
+    pub fn expect_record(&self, idx:usize)->Result<&Record> {
+        match self.get(idx) {
+            Some(Obj::Record(record)) => Ok(record),
+            _ => err_other!("Expected record at heap index {}", idx)
+        }
+    }
+
+    pub fn set_upvalue(&mut self, idx:usize, upvalue:Upvalue) {
+        if let Some(Obj::Upvalue(u)) = self.get_mut(idx) {
+            *u=upvalue;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.objects.clear();
+        self.free.clear();
+        self.bigints.clear();
+        self.live=0;
+        self.next_gc=GC_MIN;
+    }
+}
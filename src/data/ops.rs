@@ -1,5 +1,8 @@
 use std::{fmt::{Display}, vec, collections::{HashMap, hash_map::DefaultHasher}, hash::Hasher};
 use std::hash::Hash;
+use std::rc::Rc;
+use num_bigint::BigInt;
+use crate::data::obj::{Function, RecordType};
 use crate::{utils::{err::*, misc::StringIntern}, vm::{self, VM}};
 
 // Inst, Chunk, Value
@@ -16,23 +19,69 @@ pub enum Inst {
     OpReturn,
     OpConstant(usize), // idx in const pool, -> load idx onto stack
     OpGetGlobal(String), 
-    OpSetGlobal(String),
+    OpSetGlobal(String, bool), // name, is mutable
+    OpAssignGlobal(String), // errors unless the global is already defined and mutable
+    OpGetAssignGlobal(String), // get for x op= y: errors like OpAssignGlobal before reading
     OpGetLocal(usize),
     OpSetLocal(usize), // idx into value stack
     OpLoadString(u64),
+    OpBigInt(usize), // idx in chunk bigints: int literal too large for IntType
     OpIfFalseJump(usize), // jump to idx if cond is false
     OpJump(usize), // unconditional jump when branch is taken
+    OpLoop(usize), // jump back: idx of the next op to execute
     OpPrint,
     OpNegate,
     OpAdd,
     OpSub,
     OpMul,
     OpDiv,
+    OpMod,
+    OpPow,
+    OpBitAnd, // bitwise ops are on integers only
+    OpBitOr,
+    OpBitXor,
+    OpShiftLeft,
+    OpShiftRight,
     OpPop,
+    OpDup, // push a copy of the top of the stack
     OpEndScope(usize,bool), // num to pop, is_expr,
+    OpUnwind(usize), // drop everything above this many slots of the frame, closing captured locals: leaves a loop's scopes
     OpTrue,
     OpFalse,
-    OpNot
+    OpNot,
+    OpEqual,
+    OpNotEqual,
+    OpLess,
+    OpLessEq,
+    OpGreater,
+    OpGreaterEq,
+    OpClosure(usize), // idx in chunk functions -> capture its upvalues and push the closure
+    OpGetUpvalue(usize), // idx in the current closure's upvalues
+    OpSetUpvalue(usize),
+    OpCall(usize), // num args: callee is below the args on the stack
+    OpPipe(usize), // num args: like OpCall but the first arg is below the callee
+    OpRange, // start..end
+    OpList(usize), // num items on the stack to put in a new list
+    OpMap(usize), // num key, value pairs on the stack to put in a new map
+    OpTuple(usize), // num items on the stack to put in a new tuple
+    OpUnpack(usize), // tuple => its items: errors unless it has exactly this many
+    OpIndex, // list, idx => item or map, key => value
+    OpSetIndex, // list, idx, value => ()
+    OpForIter(usize, usize), // (slot of iterable followed by the idx, jump when done): push next item
+    OpTestTuple(usize), // value => whether it is a tuple of exactly this many items
+    OpTestList(usize, bool), // (num items, has rest) value => whether it is a list of this many items, or at least as many with a rest
+    OpListFrom(usize), // list => new list of its items from this idx on
+    OpNoMatch, // value => runtime error: no match arm matched it
+    OpToString, // value => its string for interpolation: strings are left as they are
+    OpType(usize), // idx in chunk types -> push the record type
+    OpRecord(Vec<String>), // field names: type followed by a value per field => new record
+    OpGetField(String), // record => value of the field
+    OpWith(Vec<String>), // field names: record followed by a value per field => copy with those fields replaced
+    OpGetFieldAt(usize), // record => value of the field at idx
+    OpTestVariant(usize), // num fields: value, type => whether value is a record of the type. A record with no fields stands for its type
+    OpImport(String), // path of the file => its module, running its code the first time it is imported
+    OpExport(String), // name of a global of the current module that importers can access
+    OpEndModule, // end of an imported file: hand its module to the importer
 }
 
 impl<'src> Display for Inst {
@@ -71,7 +120,7 @@ pub type IntType=isize;
     // Function is referred to in callframe as well but other values may only be on val stack?
 
 // store Ident(String,line) so we can retrieve for err
-#[derive(Hash, PartialEq, Eq, Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy)]
 // 16 bytes - could benefit from using &Value in stack so that everything is 8 bytes (ptr size)
 // page size: 16kb on M1 Mac -> 1024 values fit in one page
 // M1 Mac TLB=32MB -> 2^11 pages = 2048 pages
@@ -81,25 +130,103 @@ pub type IntType=isize;
 
 pub enum Value {
     Number(IntType),
+    Float(f64),
     Bool(bool),
     ObjString(u64), // change to use u64 -> Copy (hash of string in VM)
+    ObjClosure(usize), // idx in VM heap
+    ObjRange(usize),
+    ObjList(usize),
+    ObjMap(usize),
+    ObjTuple(usize),
+    ObjBigInt(usize), // ints that don't fit in IntType: never holds one that does
+    ObjType(usize), // record type
+    ObjRecord(usize),
+    ObjModule(usize), // idx in VM modules
+    Native(usize), // idx in natives::NATIVES
     Unit // empty type
 }
 
+// f64 is not Hash/Eq: floats are hashed and compared by their bits so that constants dedup correctly
+// (0.0 and -0.0 stay distinct). Numeric equality for == is handled by the VM.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Number(l), Self::Number(r)) => l == r,
+            (Self::Float(l), Self::Float(r)) => l.to_bits() == r.to_bits(),
+            (Self::Bool(l), Self::Bool(r)) => l == r,
+            (Self::ObjString(l), Self::ObjString(r)) => l == r,
+            (Self::ObjClosure(l), Self::ObjClosure(r)) => l == r,
+            (Self::ObjRange(l), Self::ObjRange(r)) => l == r,
+            (Self::ObjList(l), Self::ObjList(r)) => l == r,
+            (Self::ObjMap(l), Self::ObjMap(r)) => l == r,
+            (Self::ObjTuple(l), Self::ObjTuple(r)) => l == r,
+            (Self::ObjBigInt(l), Self::ObjBigInt(r)) => l == r, // interned in the heap
+            (Self::ObjType(l), Self::ObjType(r)) => l == r,
+            (Self::ObjRecord(l), Self::ObjRecord(r)) => l == r,
+            (Self::ObjModule(l), Self::ObjModule(r)) => l == r,
+            (Self::Native(l), Self::Native(r)) => l == r,
+            (Self::Unit, Self::Unit) => true,
+            _ => false
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Number(n) => n.hash(state),
+            Self::Float(f) => f.to_bits().hash(state),
+            Self::Bool(b) => b.hash(state),
+            Self::ObjString(s) => s.hash(state),
+            Self::ObjClosure(f) => f.hash(state),
+            Self::ObjRange(r) => r.hash(state),
+            Self::ObjList(l) => l.hash(state),
+            Self::ObjMap(m) => m.hash(state),
+            Self::ObjTuple(t) => t.hash(state),
+            Self::ObjBigInt(b) => b.hash(state),
+            Self::ObjType(t) => t.hash(state),
+            Self::ObjRecord(r) => r.hash(state),
+            Self::ObjModule(m) => m.hash(state),
+            Self::Native(n) => n.hash(state),
+            Self::Unit => ()
+        }
+    }
+}
+
 impl Value {
     pub fn num(n:IntType)->Value  {
         Self::Number(n)
     }
 
+    pub fn is_float(&self)->bool {
+        matches!(self, Self::Float(_))
+    }
+
     pub fn expect_int(&self)->Result<IntType> {
         match self {
             Self::Number(n) => Ok(*n),
+            Self::Float(_) => err_other!("Expected integer but got a float"),
+            Self::ObjBigInt(_) => err_other!("Integer too large: expected a 64-bit integer"),
             Self::Bool(b) => Ok(if *b { 1 } else { 0 }),
             Self::ObjString(_) => err_other!("Expected number but got a string"),
             _ => err_other!("Expected number but got: '{}'", self.to_string())
         }
     }
 
+    /// Ints and bools are promoted to floats
+    pub fn expect_float(&self)->Result<f64> {
+        match self {
+            Self::Float(f) => Ok(*f),
+            Self::Number(n) => Ok(*n as f64),
+            Self::Bool(b) => Ok(if *b { 1.0 } else { 0.0 }),
+            Self::ObjString(_) => err_other!("Expected number but got a string"),
+            _ => err_other!("Expected number but got: '{}'", self.to_string())
+        }
+    }
+
     pub fn expect_string(&self)->Result<u64> {
         match self {
             Self::ObjString(hash) => Ok(*hash),
@@ -107,15 +234,54 @@ impl Value {
         }
     }
 
+    pub fn expect_map(&self)->Result<usize> {
+        match self {
+            Self::ObjMap(idx) => Ok(*idx),
+            _ => err_other!("Expected map but got {}", self.type_name())
+        }
+    }
+
     pub fn expect_bool(&self)->Result<bool> {
         match self {
             Self::Bool(b) => Ok(*b),
             Self::Number(n) => Ok(!n.eq(&0)),
+            Self::Float(f) => Ok(*f != 0.0),
+            Self::ObjBigInt(_) => Ok(true), // never zero
             Self::ObjString(_) => err_other!("Expected bool but got a string"),
             _ => err_other!("Expected bool but got: {}", self.to_string())
         }
     }
 
+    /// Name of the value's type for use in error messages
+    pub fn type_name(&self)->&'static str {
+        match self {
+            Self::Number(_) => "number",
+            Self::Float(_) => "float",
+            Self::Bool(_) => "bool",
+            Self::ObjString(_) => "string",
+            Self::ObjClosure(_) => "function",
+            Self::ObjRange(_) => "range",
+            Self::ObjList(_) => "list",
+            Self::ObjMap(_) => "map",
+            Self::ObjTuple(_) => "tuple",
+            Self::ObjBigInt(_) => "number",
+            Self::ObjType(_) => "type",
+            Self::ObjRecord(_) => "record",
+            Self::ObjModule(_) => "module",
+            Self::Native(_) => "function",
+            Self::Unit => "unit"
+        }
+    }
+
+    /// Idx in the VM heap of the object the value refers to
+    pub fn heap_idx(&self)->Option<usize> {
+        match self {
+            Self::ObjClosure(idx) | Self::ObjRange(idx) | Self::ObjList(idx) | Self::ObjMap(idx) | Self::ObjTuple(idx)
+                | Self::ObjBigInt(idx) | Self::ObjType(idx) | Self::ObjRecord(idx) => Some(*idx),
+            _ => None
+        }
+    }
+
     pub fn is_unit(&self)->bool {
         match self {
             Self::Unit => true,
@@ -135,8 +301,19 @@ impl Display for Value  {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let repr=match &self {
             Self::Number(n) => n.to_string(),
+            Self::Float(n) => format!("{:?}", n), // keep the '.0' for whole floats
             Self::Bool(b) => b.to_string(),
             Self::ObjString(s) => format!("\"{}\"", s.to_string()),
+            Self::ObjClosure(_) => String::from("<fn>"),
+            Self::ObjRange(_) => String::from("<range>"),
+            Self::ObjList(_) => String::from("<list>"),
+            Self::ObjMap(_) => String::from("<map>"),
+            Self::ObjTuple(_) => String::from("<tuple>"),
+            Self::ObjBigInt(_) => String::from("<bigint>"),
+            Self::ObjType(_) => String::from("<type>"),
+            Self::ObjRecord(_) => String::from("<record>"),
+            Self::ObjModule(_) => String::from("<module>"),
+            Self::Native(_) => String::from("<native fn>"),
             Self::Unit => String::from("()")
         };
 
@@ -192,14 +369,17 @@ pub struct Chunk {
     constants_map:HashMap<u64,usize>, // val.hash->idx stored in constants
     op_lines:Lines, // line numbers
     constant_lines:Lines, // two arrs because index goes along with the enum (less confusing),
-    pub strings:StringIntern
+    pub strings:StringIntern,
+    functions:Vec<Rc<Function>>, // functions declared in this chunk
+    types:Vec<Rc<RecordType>>, // record types declared in this chunk
+    bigints:Vec<BigInt> // int literals too large for IntType
 }
 
 impl<'src> Chunk {
     pub fn new()->Self {
         Chunk {
             ops:vec![], constants:vec![], op_lines:Lines::new(), constant_lines:Lines::new(), constants_map:HashMap::new(),
-            strings:StringIntern::new()
+            strings:StringIntern::new(), functions:vec![], types:vec![], bigints:vec![]
         }
     }
 
@@ -277,6 +457,36 @@ impl<'src> Chunk {
         self.write_op(op, line);
     }
 
+    /// Returns index of the big int for use in OpBigInt
+    pub fn add_bigint(&mut self, n:BigInt)->usize {
+        self.bigints.push(n);
+        self.bigints.len()-1
+    }
+
+    pub fn get_bigint(&self, idx:usize)->Option<&BigInt> {
+        self.bigints.get(idx)
+    }
+
+    /// Returns index of the record type for use in OpType
+    pub fn add_type(&mut self, ty:RecordType)->usize {
+        self.types.push(Rc::new(ty));
+        self.types.len()-1
+    }
+
+    pub fn get_type(&self, idx:usize)->Option<Rc<RecordType>> {
+        self.types.get(idx).cloned()
+    }
+
+    /// Returns index of function for use in OpClosure
+    pub fn add_function(&mut self, function:Function)->usize {
+        self.functions.push(Rc::new(function));
+        self.functions.len()-1
+    }
+
+    pub fn get_function(&self, idx:usize)->Option<Rc<Function>> {
+        self.functions.get(idx).cloned()
+    }
+
     pub fn get_line_of_constant(&self, idx:usize) -> Option<usize>{
         self.constant_lines.get_line(idx)
     }
@@ -286,6 +496,12 @@ impl<'src> Chunk {
     }
 }
 
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'src> Display for Chunk {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut code=String::from("Ops:\n");
@@ -310,6 +526,11 @@ impl<'src> Display for Chunk {
             code.push_str(fmt.as_str());
         }
 
+        for function in self.functions.iter() {
+            let fmt=format!("\n\nFunction {}:\n{}", function.name, function.chunk);
+            code.push_str(fmt.as_str());
+        }
+
         write!(f, "{}", code)
     }
 }
@@ -334,4 +555,19 @@ fn test_lines() {
     assert_eq!(Some(15),lines.get_line(5));
     assert_eq!(None, lines.get_line(6));
 
+}
+
+#[test]
+fn test_float_constants() {
+    let mut chunk=Chunk::new();
+    let a=chunk.add_constant(Value::Float(1.5), 1);
+    let b=chunk.add_constant(Value::Float(1.5), 1);
+    let c=chunk.add_constant(Value::Number(1), 1);
+    let d=chunk.add_constant(Value::Float(1.0), 1);
+    let e=chunk.add_constant(Value::Float(-0.0), 1);
+    let f=chunk.add_constant(Value::Float(0.0), 1);
+
+    assert_eq!(a, b);
+    assert_ne!(c, d);
+    assert_ne!(e, f);
 }
\ No newline at end of file
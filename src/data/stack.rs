@@ -76,11 +76,27 @@ impl<T:Copy + Debug> FixedStack <T> {
     pub fn set(&mut self, idx:usize, item:T) {
         self.stack[idx]=Some(item);
     }
+
+    /// Number of items on the stack
+    pub fn len(&self)->usize {
+        self.stack_top
+    }
+
+    pub fn is_empty(&self)->bool {
+        self.stack_top == 0
+    }
+
+    /// Drop everything from idx upwards
+    pub fn truncate(&mut self, idx:usize) {
+        if idx < self.stack_top {
+            self.stack_top=idx;
+        }
+    }
 }
 
 impl<T:Copy + Debug> Stack<T> for FixedStack <T> {
     fn push(&mut self,val: T)->Result<()>{
-        if self.stack_top > STACK_SIZE {
+        if self.stack_top >= STACK_SIZE {
             return errn!("Maximum stack size {} exceeded: stack overflow", STACK_SIZE);
         }
 
@@ -153,6 +169,14 @@ impl <T> VecStack<T> {
         self.stack.last()
     }
 
+    pub fn peek_mut(&mut self) -> Option<&mut T> {
+        self.stack.last_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
     pub fn clear(&mut self) {
         self.stack.clear()
     }
@@ -5,6 +5,7 @@ extern crate lazy_static;
 pub mod data;
 pub mod utils;
 pub mod vm;
+pub mod natives;
 pub mod compiler;
 pub mod scanner;
 pub mod parser;
@@ -163,6 +164,13 @@ pub fn test_input_many(v:&Vec<(&str, &str)>) {
     }
 }
 
+/// Like test_input_many with prefix run before each input e.g declarations the cases share
+pub fn test_input_many_with_prefix(prefix:&str, v:&[(&str, &str)]) {
+    for (lhs, rhs) in v.iter() {
+        test_input(&format!("{} {}", prefix, lhs), rhs);
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use crate::data::ops::*;
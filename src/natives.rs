@@ -0,0 +1,70 @@
+use crate::data::ops::*;
+use crate::utils::err::*;
+use crate::vm::VM;
+
+// Functions implemented in Rust that scripts call like their own e.g keys(m)
+// A global with the same name shadows the native
+
+pub type NativeFn=fn(&mut VM, &[Value])->Result<Value>;
+
+#[derive(Debug)]
+pub struct Native {
+    pub name:&'static str,
+    pub arity:usize,
+    pub function:NativeFn
+}
+
+pub const NATIVES:[Native; 5]=[
+    Native { name: "len", arity: 1, function: len },
+    Native { name: "has", arity: 2, function: has },
+    Native { name: "keys", arity: 1, function: keys },
+    Native { name: "values", arity: 1, function: values },
+    Native { name: "remove", arity: 2, function: remove },
+];
+
+/// Value for the native called name if any
+pub fn find_native(name:&str)->Option<Value> {
+    NATIVES.iter()
+        .position(|native| native.name==name)
+        .map(Value::Native)
+}
+
+/// Number of items in a list, tuple or map, or of chars in a string
+fn len(vm:&mut VM, args:&[Value])->Result<Value> {
+    let len=match args[0] {
+        Value::ObjList(obj) => vm.heap.expect_list(obj)?.len(),
+        Value::ObjTuple(obj) => vm.heap.expect_tuple(obj)?.len(),
+        Value::ObjMap(obj) => vm.heap.expect_map(obj)?.len(),
+        Value::ObjString(hash) => vm.strings.get_string(hash).map(|s| s.chars().count()).unwrap_or(0),
+        value => {
+            let msg=format!("Cannot get length of {}", value.type_name());
+            return err_other!(msg);
+        }
+    };
+
+    Ok(Value::Number(len as IntType))
+}
+
+fn has(vm:&mut VM, args:&[Value])->Result<Value> {
+    let key=vm.heap.key(args[1])?;
+    let map=vm.heap.expect_map(args[0].expect_map()?)?;
+    Ok(Value::Bool(map.has(&key)))
+}
+
+/// Keys of a map in insertion order
+fn keys(vm:&mut VM, args:&[Value])->Result<Value> {
+    let keys=vm.heap.expect_map(args[0].expect_map()?)?.keys();
+    Ok(vm.alloc_list(keys))
+}
+
+fn values(vm:&mut VM, args:&[Value])->Result<Value> {
+    let values=vm.heap.expect_map(args[0].expect_map()?)?.values();
+    Ok(vm.alloc_list(values))
+}
+
+/// Remove key from a map: returns its value, or unit if it wasn't there
+fn remove(vm:&mut VM, args:&[Value])->Result<Value> {
+    let key=vm.heap.key(args[1])?;
+    let map=vm.heap.expect_map_mut(args[0].expect_map()?)?;
+    Ok(map.remove(&key).unwrap_or(Value::Unit))
+}
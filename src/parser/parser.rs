@@ -1,8 +1,14 @@
-use crate::compiler::Compiler;
+use std::collections::{HashMap, HashSet};
+
+use num_bigint::BigInt;
+
+use crate::compiler::{Compiler, FunctionType};
+use crate::data::obj::{Function, RecordType};
 use crate::scanner::delim::{Delimiter, DelimiterScanner};
-use crate::scanner::{tokens::*, Scanner};
+use crate::scanner::{tokens::*, Scanner, expr_len};
 use crate::data::ops::*;
 use crate::utils::err::*;
+use crate::utils::constants::{BACKSLASH, LEFT_BRACE, OPEN_STRING, RIGHT_BRACE};
 
 use Inst::*;
 
@@ -23,7 +29,70 @@ pub struct Parser<'src> {
     curr_tok:Option<Token<'src>>,
     line:usize,
     delim_scanner:DelimiterScanner,
-    is_stmt:bool // set to true when semicolon consumed
+    is_stmt:bool, // set to true when semicolon consumed
+    bare_params:bool, // false inside argument lists, where x, y -> e would be ambiguous
+    last_call:Option<usize>, // idx of the OpCall for the last call written, so a pipe can add its arg
+    fixities:HashMap<String, Fixity>, // declared precedence of $name operators
+    type_names:HashSet<String>, // record types and variants declared so far: a bare Name in a pattern tests for one of them
+    loops:Vec<LoopContext>, // loops being compiled in the current function, innermost last
+    exporting:bool // set while compiling a declaration after export
+}
+
+/// Where break and continue in a loop jump to
+#[derive(Debug)]
+struct LoopContext {
+    depth:usize, // scope depth of the loop's hidden locals: break, continue and each iteration unwind everything above them
+    start:usize, // continue jumps here
+    result_slot:usize, // local set by break value
+    breaks:Vec<usize> // jumps to patch to the end of the loop
+}
+
+/// Precedence and associativity of a user defined infix operator e.g infixr 5 $cons;
+#[derive(Debug, Clone, Copy)]
+pub struct Fixity {
+    prec:Precedence,
+    is_right:bool
+}
+
+/// Pattern of a match arm
+#[derive(Debug)]
+enum Pattern<'src> {
+    Wildcard, // _
+    Bind(Token<'src>), // name: matches anything and binds it
+    Value(Value), // number, bool or ()
+    BigInt(BigInt), // int too large for IntType
+    Str(String),
+    Tuple(Vec<Pattern<'src>>),
+    List(Vec<Pattern<'src>>, Option<Box<Pattern<'src>>>), // items, then ..rest for any further items
+    Variant(Token<'src>, Vec<Pattern<'src>>) // Name(fields) or Name: a record of type Name
+}
+
+/// Step from a part of a match subject to one of its own parts
+#[derive(Debug, Clone, Copy)]
+enum PathStep {
+    Item(usize), // item of a tuple or list
+    Field(usize) // field of a record
+}
+
+/// Step to the item at idx of a tuple, list or variant pattern
+fn pattern_step(pattern:&Pattern, idx:usize)->PathStep {
+    match pattern {
+        Pattern::Variant(..) => PathStep::Field(idx),
+        _ => PathStep::Item(idx)
+    }
+}
+
+/// Capitalised names are types and constructors: Name { .. } makes a record and Name(..) in a pattern tests for one
+fn is_type_name(name:&str)->bool {
+    name.starts_with(|c:char| c.is_ascii_uppercase())
+}
+
+/// Pattern for an int literal: big when it doesn't fit in IntType
+fn int_pattern<'src>(content:&str)->Pattern<'src> {
+    match content.parse::<IntType>() {
+        Ok(n) => Pattern::Value(Value::Number(n)),
+        Err(_) => Pattern::BigInt(content.parse().unwrap())
+    }
 }
 
 /*
@@ -39,12 +108,13 @@ impl<'src> Parser<'src> {
 
         let delimiters:Vec<Delimiter> = vec![
             Delimiter::new(TokenLeftParen, TokenRightParen, false),
+            Delimiter::new(TokenLeftBracket, TokenRightBracket, false),
             Delimiter::new(TokenStringQuote, TokenStringQuote, true)
         ];
 
         let delim_scanner=DelimiterScanner::new(delimiters);
 
-        Parser { scanner, compiler, prev_tok: None, curr_tok: None, line:1, delim_scanner, is_stmt:true }
+        Parser { scanner, compiler, prev_tok: None, curr_tok: None, line:1, delim_scanner, is_stmt:true, bare_params:true, last_call:None, fixities:HashMap::new(), type_names:HashSet::new(), loops:vec![], exporting:false }
     }
 
     // ParseFn: assume that the token to parse is set in self.prev
@@ -52,11 +122,22 @@ impl<'src> Parser<'src> {
     // expect_token_type(ty)->Result<()>
     pub fn number(&mut self, chunk: &mut Chunk)->Result<()>{
         let prev=self.expect_prev()?;
-        self.expect_token_type(prev, TokenInteger, "integer")?; // only errs when bug in parser
 
         // convert to number
-        let value:IntType = prev.content.parse().unwrap();
-        let value=Value::Number(value);
+        let value=match prev.token_type {
+            TokenFloat => Value::Float(prev.content.parse().unwrap()),
+            _ => {
+                self.expect_token_type(prev, TokenInteger, "integer")?; // only errs when bug in parser
+                match prev.content.parse::<IntType>() {
+                    Ok(value) => Value::Number(value),
+                    Err(_) => {
+                        let idx=chunk.add_bigint(prev.content.parse().unwrap());
+                        chunk.write_op(OpBigInt(idx), prev.line);
+                        return Ok(());
+                    }
+                }
+            }
+        };
 
         chunk.write_constant(value, prev.line);
         Ok(())
@@ -89,7 +170,9 @@ impl<'src> Parser<'src> {
         let rule=ParseRule::get_rule(prev.token_type);
         
         // put right side onto stack - use next higher precedence for left associativity
-        self.parse_precedence(chunk, rule.prec.get_next_prec())?;
+        // ** is right associative: 2 ** 3 ** 2 is 2 ** (3 ** 2), and its right side can be unary e.g 2 ** -1
+        let rhs_prec=if prev.token_type==TokenStarStar { rule.prec.get_prev_prec() } else { rule.prec.get_next_prec() };
+        self.with_temps(1, |parser| parser.parse_precedence(chunk, rhs_prec))?;
 
         if rule.infix.is_none() {
             let msg=format!("Expected operation but got {}", prev);
@@ -102,6 +185,20 @@ impl<'src> Parser<'src> {
             TokenMinus => OpSub,
             TokenStar => OpMul,
             TokenSlash => OpDiv,
+            TokenPercent => OpMod,
+            TokenStarStar => OpPow,
+            TokenAmpersand => OpBitAnd,
+            TokenBar => OpBitOr,
+            TokenCaret => OpBitXor,
+            TokenShiftLeft => OpShiftLeft,
+            TokenShiftRight => OpShiftRight,
+            TokenEqEq => OpEqual,
+            TokenNotEq => OpNotEqual,
+            TokenLess => OpLess,
+            TokenLessEq => OpLessEq,
+            TokenGt => OpGreater,
+            TokenGtEq => OpGreaterEq,
+            TokenRange => OpRange,
             _ => return self.report_msg(prev, "Unrecognised operation")
 
         };
@@ -138,101 +235,1133 @@ impl<'src> Parser<'src> {
 
         debug!("Ip after else: {}", chunk.get_ip().unwrap());
 
-        let jmp_after_if=chunk.get_ip().unwrap();
-        let jmp=chunk.get_op_mut(jmp_idx).unwrap();
-        match jmp {
-            OpJump(k) => {
-                *k=jmp_after_if;
+        // OpJump skips past the else, OpIfFalseJump lands right after the OpJump
+        self.patch_jump(chunk, jmp_idx);
+        self.patch_jump_to(chunk, if_false_idx, jmp_idx);
+
+        debug!("HERE");
+
+        
+  
+        // self.is_stmt=true;
+
+
+        // stmt: other statements can follow this
+        // because of this op return is not emitted so the last value remains on the stack
+        self.is_stmt=true; 
+
+        // // self.advance();
+        // self.expression(chunk)?;
+
+        Ok(())
+    }
+
+
+    // while (cond) { body }: the loop's value is given by break, unit otherwise
+    fn while_loop(&mut self, chunk:&mut Chunk)->Result<()> {
+        self.begin_loop(chunk);
+        let loop_start=chunk.get_ip().map(|ip| ip+1).unwrap_or(0);
+
+        self.consume(TokenLeftParen)?;
+        self.sub_expression(chunk)?;
+        self.consume(TokenRightParen)?;
+
+        let exit_jmp=chunk.write_op(OpIfFalseJump(0), self.line);
+        self.loop_body(chunk, loop_start)?;
+
+        self.patch_jump(chunk, exit_jmp);
+        self.end_loop(chunk);
+        Ok(())
+    }
+
+    // for x in iterable { body }
+    fn for_loop(&mut self, chunk:&mut Chunk)->Result<()> {
+        let var=self.consume(TokenIdent)?;
+        self.consume(TokenIn)?;
+
+        // hidden locals: the iterable and the idx of the next item
+        self.begin_loop(chunk);
+        self.sub_expression(chunk)?;
+        let iter_slot=self.compiler.add_local("").unwrap();
+        chunk.write_constant(Value::Number(0), self.line);
+        self.compiler.add_local("");
+
+        let loop_start=chunk.get_ip().map(|ip| ip+1).unwrap_or(0);
+        let exit_jmp=chunk.write_op(OpForIter(iter_slot, 0), self.line);
+
+        // item pushed by OpForIter is a fresh local for each iteration
+        self.compiler.begin_scope();
+        self.compiler.add_local(var.content);
+        self.loop_body(chunk, loop_start)?;
+
+        self.patch_jump(chunk, exit_jmp);
+        self.end_loop(chunk);
+        Ok(())
+    }
+
+    /// Scope of a loop: its first local is the loop's result, unit unless set by break
+    fn begin_loop(&mut self, chunk:&mut Chunk) {
+        self.compiler.begin_scope();
+        chunk.write_constant(Value::Unit, self.line);
+        let result_slot=self.compiler.add_local("").expect("Loop result must be a local");
+
+        let depth=self.compiler.depth();
+        self.loops.push(LoopContext { depth, start: 0, result_slot, breaks: vec![] });
+    }
+
+    /// Compile the block run on each iteration then jump back to loop_start
+    fn loop_body(&mut self, chunk:&mut Chunk, loop_start:usize)->Result<()> {
+        if !self.check_tok(TokenLeftBrace) {
+            return self.report_err("Expected '{' before loop body");
+        }
+
+        let ctx=self.loops.last_mut().expect("Loop context missing");
+        ctx.start=loop_start;
+        let depth=ctx.depth;
+
+        self.is_stmt=true;
+        self.expression(chunk)?;
+
+        // for: drop the loop var
+        self.is_stmt=true;
+        if self.compiler.depth() > depth {
+            self.compiler.end_scope();
+        }
+
+        // the body's value and what an if branch left behind e.g { if (c) { 1 } } would pile up every iteration
+        chunk.write_op(OpUnwind(self.compiler.slots_at(depth)), self.line);
+        chunk.write_op(OpLoop(loop_start), self.line);
+        Ok(())
+    }
+
+    /// Land breaks after the loop, then pop the loop's locals except its result which is left on the stack
+    fn end_loop(&mut self, chunk:&mut Chunk) {
+        let ctx=self.loops.pop().expect("Loop context missing");
+        for idx in ctx.breaks {
+            self.patch_jump(chunk, idx);
+        }
+
+        let count=self.compiler.end_scope();
+        if count > 1 {
+            chunk.write_op(OpEndScope(count-1, false), self.line);
+        }
+        self.is_stmt=false;
+    }
+
+    // break expression? ';'
+    fn break_statement(&mut self, chunk:&mut Chunk)->Result<()> {
+        let tok=self.expect_prev()?;
+        let (depth, result_slot)=match self.loops.last() {
+            Some(ctx) => (ctx.depth, ctx.result_slot),
+            None => return self.report_msg(tok, "Can't break outside of a loop")
+        };
+
+        if !self.check_tok(TokenSemiColon) {
+            self.sub_expression(chunk)?;
+            chunk.write_op(OpSetLocal(result_slot), tok.line);
+            chunk.write_op(OpPop, tok.line);
+        }
+        self.consume(TokenSemiColon)?;
+
+        // pop every scope being left along with unfinished operands e.g [1, { break; }]
+        chunk.write_op(OpUnwind(self.compiler.slots_at(depth)), tok.line);
+        let idx=chunk.write_op(OpJump(0), tok.line);
+        self.loops.last_mut().unwrap().breaks.push(idx);
+        Ok(())
+    }
+
+    // continue ';'
+    fn continue_statement(&mut self, chunk:&mut Chunk)->Result<()> {
+        let tok=self.expect_prev()?;
+        let (depth, start)=match self.loops.last() {
+            Some(ctx) => (ctx.depth, ctx.start),
+            None => return self.report_msg(tok, "Can't continue outside of a loop")
+        };
+        self.consume(TokenSemiColon)?;
+
+        chunk.write_op(OpUnwind(self.compiler.slots_at(depth)), tok.line);
+        chunk.write_op(OpLoop(start), tok.line);
+        Ok(())
+    }
+
+    /// Loop or match in statement position: its value is discarded unless it ends a block, where it is the block's value
+    fn loop_statement(&mut self, chunk:&mut Chunk)->Result<()> {
+        if self.check_tok(TokenRightBrace) {
+            return Ok(());
+        }
+
+        self.match_token(TokenSemiColon);
+        chunk.write_op(OpPop, self.line);
+        self.is_stmt=true;
+        Ok(())
+    }
+
+    // match subject { pattern (if guard)? => expression, ... }
+    // arms are tried in order: a failed test jumps to the next arm, the last one fails with a runtime error
+    fn match_expression(&mut self, chunk:&mut Chunk)->Result<()> {
+        let line=self.line;
+
+        // subject is a hidden local that the arms' tests read from
+        self.compiler.begin_scope();
+        self.sub_expression(chunk)?;
+        let subject=self.compiler.add_local("").expect("Match subject must be a local");
+
+        self.consume(TokenLeftBrace)?;
+        let bare_params=std::mem::replace(&mut self.bare_params, false);
+
+        let mut ends=vec![];
+        while !self.check_tok(TokenRightBrace) {
+            let end=self.match_arm(chunk, subject)?;
+            ends.push(end);
+
+            if !self.match_token(TokenComma) {
+                break;
+            }
+        }
+
+        self.bare_params=bare_params;
+        self.consume(TokenRightBrace)?;
+
+        chunk.write_op(OpGetLocal(subject), line);
+        chunk.write_op(OpNoMatch, line);
+
+        for idx in ends {
+            self.patch_jump(chunk, idx);
+        }
+
+        let count=self.compiler.end_scope();
+        chunk.write_op(OpEndScope(count, true), line);
+        self.is_stmt=false;
+        Ok(())
+    }
+
+    /// Compile one arm: returns the idx of the jump to the end of the match taken after its body
+    fn match_arm(&mut self, chunk:&mut Chunk, subject:usize)->Result<usize> {
+        let pattern=self.pattern()?;
+
+        // bindings are locals of the arm
+        self.compiler.begin_scope();
+        let mut fails=vec![];
+        self.pattern_tests(chunk, &pattern, subject, &mut vec![], &mut fails);
+        self.pattern_bindings(chunk, &pattern, subject, &mut vec![]);
+
+        let guard=if self.match_token(TokenIf) {
+            self.sub_expression(chunk)?;
+            Some(chunk.write_op(OpIfFalseJump(0), self.line))
+        } else {
+            None
+        };
+
+        self.consume(TokenFatArrow)?;
+        self.sub_expression(chunk)?;
+
+        // block ending in a statement
+        if self.is_stmt {
+            chunk.write_constant(Value::Unit, self.line);
+        }
+
+        let count=self.compiler.end_scope();
+        if count > 0 {
+            chunk.write_op(OpEndScope(count, true), self.line);
+        }
+        let end=chunk.write_op(OpJump(0), self.line);
+
+        // a failed guard pops the bindings before trying the next arm
+        if let Some(idx) = guard {
+            self.patch_jump(chunk, idx);
+            if count > 0 {
+                chunk.write_op(OpEndScope(count, false), self.line);
+            }
+        }
+
+        for idx in fails {
+            self.patch_jump(chunk, idx);
+        }
+
+        Ok(end)
+    }
+
+    // _ | name | literal | -number | ( pattern (, pattern)* ) | [ pattern (, pattern)* (, ..name?)? ] | Name ( ( pattern (, pattern)* )? )?
+    fn pattern(&mut self)->Result<Pattern<'src>> {
+        let tok=match self.curr_tok {
+            Some(tok) => tok,
+            None => return self.report_err("Expected pattern but got end of input.").map(|_| Pattern::Wildcard)
+        };
+        self.advance()?;
+
+        let pattern=match tok.token_type {
+            TokenIdent if tok.content=="_" => Pattern::Wildcard,
+            // a bare Name is a variant only if one was declared, else it binds like any name
+            TokenIdent if is_type_name(tok.content) && (self.check_tok(TokenLeftParen) || self.type_names.contains(tok.content)) => {
+                let mut items=vec![];
+                if self.match_token(TokenLeftParen) {
+                    while !self.check_tok(TokenRightParen) {
+                        items.push(self.pattern()?);
+                        if !self.match_token(TokenComma) {
+                            break;
+                        }
+                    }
+                    self.consume(TokenRightParen)?;
+                }
+                Pattern::Variant(tok, items)
             },
-            _ => unreachable!()
+            TokenIdent => Pattern::Bind(tok),
+            TokenInteger => int_pattern(tok.content),
+            TokenFloat => Pattern::Value(Value::Float(tok.content.parse().unwrap())),
+            TokenTrue => Pattern::Value(Value::Bool(true)),
+            TokenFalse => Pattern::Value(Value::Bool(false)),
+            TokenMinus => {
+                let num=self.consume_one_of(vec![TokenInteger, TokenFloat])?;
+                match num.token_type {
+                    TokenFloat => Pattern::Value(Value::Float(-num.content.parse::<f64>().unwrap())),
+                    _ => int_pattern(&format!("-{}", num.content))
+                }
+            },
+            TokenStringQuote => {
+                let string=self.consume_one_of(vec![TokenString, TokenStringQuote])?;
+                if string.token_type==TokenStringQuote {
+                    Pattern::Str(String::new())
+                } else {
+                    let (text, rest)=self.string_parts(string)?;
+                    if !rest.is_empty() {
+                        return self.report_msg(string, "Can't interpolate in a pattern").map(|_| Pattern::Wildcard);
+                    }
+
+                    self.consume(TokenStringQuote)?;
+                    Pattern::Str(text)
+                }
+            },
+            TokenLeftParen => {
+                if self.match_token(TokenRightParen) {
+                    return Ok(Pattern::Value(Value::Unit));
+                }
+
+                // (p) is just p, (p,) is a 1-tuple
+                let first=self.pattern()?;
+                if !self.match_token(TokenComma) {
+                    self.consume(TokenRightParen)?;
+                    return Ok(first);
+                }
+
+                let mut items=vec![first];
+                while !self.check_tok(TokenRightParen) {
+                    items.push(self.pattern()?);
+                    if !self.match_token(TokenComma) {
+                        break;
+                    }
+                }
+                self.consume(TokenRightParen)?;
+                Pattern::Tuple(items)
+            },
+            TokenLeftBracket => {
+                let mut items=vec![];
+                let mut rest=None;
+
+                while !self.check_tok(TokenRightBracket) {
+                    // ..rest or .. must be last
+                    if self.match_token(TokenRange) {
+                        let name=if self.check_tok(TokenIdent) { Some(self.consume(TokenIdent)?) } else { None };
+                        rest=Some(Box::new(match name {
+                            Some(name) if name.content!="_" => Pattern::Bind(name),
+                            _ => Pattern::Wildcard
+                        }));
+                        break;
+                    }
+
+                    items.push(self.pattern()?);
+                    if !self.match_token(TokenComma) {
+                        break;
+                    }
+                }
+                self.consume(TokenRightBracket)?;
+                Pattern::List(items, rest)
+            },
+            _ => {
+                let msg=format!("Expected pattern but got {}", tok);
+                return self.report_msg(tok, msg).map(|_| Pattern::Wildcard);
+            }
+        };
+
+        Ok(pattern)
+    }
+
+    /// Push the part of the subject at path e.g [1, 0] is subject[1][0]
+    fn load_path(&self, chunk:&mut Chunk, subject:usize, path:&[PathStep]) {
+        chunk.write_op(OpGetLocal(subject), self.line);
+        for step in path {
+            match step {
+                PathStep::Item(idx) => {
+                    chunk.write_constant(Value::Number(*idx as IntType), self.line);
+                    chunk.write_op(OpIndex, self.line);
+                },
+                PathStep::Field(idx) => {
+                    chunk.write_op(OpGetFieldAt(*idx), self.line);
+                }
+            }
         }
+    }
 
-        // update iffalsejump to be after opjump
-        let if_false_jmp=chunk.get_op_mut(if_false_idx).unwrap();
-        match if_false_jmp {
-            OpIfFalseJump(k) => {
-                *k=jmp_idx;
+    /// Checks that the part of the subject at path matches: each failure jumps to the next arm
+    fn pattern_tests(&mut self, chunk:&mut Chunk, pattern:&Pattern<'src>, subject:usize, path:&mut Vec<PathStep>, fails:&mut Vec<usize>) {
+        let items=match pattern {
+            Pattern::Wildcard | Pattern::Bind(_) => return,
+            Pattern::Value(value) => {
+                self.load_path(chunk, subject, path);
+                chunk.write_constant(*value, self.line);
+                chunk.write_op(OpEqual, self.line);
+                fails.push(chunk.write_op(OpIfFalseJump(0), self.line));
+                return;
+            },
+            Pattern::BigInt(n) => {
+                self.load_path(chunk, subject, path);
+                let idx=chunk.add_bigint(n.clone());
+                chunk.write_op(OpBigInt(idx), self.line);
+                chunk.write_op(OpEqual, self.line);
+                fails.push(chunk.write_op(OpIfFalseJump(0), self.line));
+                return;
+            },
+            Pattern::Str(string) => {
+                self.load_path(chunk, subject, path);
+                chunk.load_string(string.clone(), self.line);
+                chunk.write_op(OpEqual, self.line);
+                fails.push(chunk.write_op(OpIfFalseJump(0), self.line));
+                return;
+            },
+            Pattern::Tuple(items) => {
+                self.load_path(chunk, subject, path);
+                chunk.write_op(OpTestTuple(items.len()), self.line);
+                items
+            },
+            Pattern::List(items, rest) => {
+                self.load_path(chunk, subject, path);
+                chunk.write_op(OpTestList(items.len(), rest.is_some()), self.line);
+                items
+            },
+            Pattern::Variant(name, items) => {
+                self.load_path(chunk, subject, path);
+                let get_op=self.resolve_variable(name.content);
+                chunk.write_op(get_op, name.line);
+                chunk.write_op(OpTestVariant(items.len()), name.line);
+                items
+            }
+        };
+
+        // items are only indexed once the shape is known to match
+        fails.push(chunk.write_op(OpIfFalseJump(0), self.line));
+        for (idx, item) in items.iter().enumerate() {
+            path.push(pattern_step(pattern, idx));
+            self.pattern_tests(chunk, item, subject, path, fails);
+            path.pop();
+        }
+    }
+
+    /// Declare a local for each name in a pattern that is known to match
+    fn pattern_bindings(&mut self, chunk:&mut Chunk, pattern:&Pattern<'src>, subject:usize, path:&mut Vec<PathStep>) {
+        match pattern {
+            Pattern::Bind(name) => {
+                self.load_path(chunk, subject, path);
+                self.compiler.add_local(name.content);
+            },
+            Pattern::Tuple(items) | Pattern::List(items, _) | Pattern::Variant(_, items) => {
+                for (idx, item) in items.iter().enumerate() {
+                    path.push(pattern_step(pattern, idx));
+                    self.pattern_bindings(chunk, item, subject, path);
+                    path.pop();
+                }
+
+                if let Pattern::List(items, Some(rest)) = pattern {
+                    if let Pattern::Bind(name) = **rest {
+                        self.load_path(chunk, subject, path);
+                        chunk.write_op(OpListFrom(items.len()), self.line);
+                        self.compiler.add_local(name.content);
+                    }
+                }
+            },
+            _ => ()
+        }
+    }
+
+    // left operand is on the stack: it is the result if it is false, otherwise the right operand is
+    fn and(&mut self, chunk:&mut Chunk)->Result<()> {
+        chunk.write_op(OpDup, self.line);
+        let end_jmp=chunk.write_op(OpIfFalseJump(0), self.line);
+
+        chunk.write_op(OpPop, self.line);
+        self.parse_precedence(chunk, PrecAnd.get_next_prec())?;
+
+        self.patch_jump(chunk, end_jmp);
+        Ok(())
+    }
+
+    // left operand is on the stack: it is the result if it is true, otherwise the right operand is
+    fn or(&mut self, chunk:&mut Chunk)->Result<()> {
+        chunk.write_op(OpDup, self.line);
+        let else_jmp=chunk.write_op(OpIfFalseJump(0), self.line);
+        let end_jmp=chunk.write_op(OpJump(0), self.line);
+
+        self.patch_jump(chunk, else_jmp);
+        chunk.write_op(OpPop, self.line);
+        self.parse_precedence(chunk, PrecOr.get_next_prec())?;
+
+        self.patch_jump(chunk, end_jmp);
+        Ok(())
+    }
+
+    // infix for $: a $f b is f(a, b)
+    fn infix_call(&mut self, chunk:&mut Chunk)->Result<()> {
+        let name=self.consume(TokenIdent)?;
+        let fixity=self.fixity(name);
+
+        let get_op=self.resolve_variable(name.content);
+        chunk.write_op(get_op, name.line);
+
+        // right associative: an operator of the same precedence in rhs is parsed first
+        let rhs_prec=if fixity.is_right { fixity.prec } else { fixity.prec.get_next_prec() };
+        self.with_temps(2, |parser| parser.parse_precedence(chunk, rhs_prec))?;
+
+        // a, f, b => f, a, b then call
+        chunk.write_op(OpPipe(2), name.line);
+        Ok(())
+    }
+
+    /// Fixity of the operator named by token: left associative at PrecFactor if not declared
+    fn fixity(&self, name:Token<'src>)->Fixity {
+        self.fixities.get(name.content)
+            .copied()
+            .unwrap_or(Fixity { prec: ParseRule::get_rule(TokenInfix).prec, is_right: false })
+    }
+
+    /// Patch jump at idx so that execution continues after the last op written
+    fn patch_jump(&self, chunk:&mut Chunk, idx:usize) {
+        let target=chunk.get_ip().unwrap();
+        self.patch_jump_to(chunk, idx, target);
+    }
+
+    /// Patch jump at idx so that execution continues after the op at target
+    fn patch_jump_to(&self, chunk:&mut Chunk, idx:usize, target:usize) {
+        match chunk.get_op_mut(idx) {
+            Some(OpJump(k)) | Some(OpIfFalseJump(k)) | Some(OpForIter(_, k)) => {
+                *k=target;
             },
             _ => unreachable!()
         }
+    }
 
-        debug!("HERE");
+    fn expression(&mut self, chunk:&mut Chunk)->Result<()>{
+        // assign is the lowest valid precedence: other ops can bind as much as possibl
+        debug!("EXPRESSION {:?}", self);
+        // Block expression
+        if self.match_token(TokenLeftBrace) {
+            self.begin_scope(chunk)?;
+            self.block_expression(chunk)?;
+            self.end_scope(chunk)?;
+            return Ok(())
+        } 
 
-        
-  
-        // self.is_stmt=true;
+        if !self.is_stmt {
+            return self.report_err("Expressions not allowed immediately after another expression.")
+        }
+
+        self.parse_precedence(chunk, PrecAssign)?;
+
+        // is_stmt if prev tok is semicolon - for "x=5;"
+        self.is_stmt=self.expect_prev()
+            .ok()
+            .map(|tok| tok.token_type.eq(&TokenSemiColon))
+            .unwrap_or(false);
+        Ok(())
+    }
+
+    /// Expression nested inside another e.g grouping or argument: it starts fresh so it can't follow a previous expression
+    fn sub_expression(&mut self, chunk:&mut Chunk)->Result<()> {
+        self.is_stmt=true;
+        self.expression(chunk)
+    }
+
+    /// Parse with count values already on the stack for the current expression,
+    /// so that locals declared meanwhile e.g in a block or loop get the right slots
+    fn with_temps<T>(&mut self, count:usize, parse:impl FnOnce(&mut Parser<'src>)->Result<T>)->Result<T> {
+        self.compiler.push_temps(count);
+        let res=parse(self);
+        self.compiler.pop_temps(count);
+        res
+    }
+
+    fn grouping(&mut self, chunk:&mut Chunk)->Result<()> {
+        if self.match_token(TokenLet) {
+            return self.let_expression(chunk);
+        }
+
+        // (x, y) -> body
+        if self.lambda_ahead(TokenRightParen) {
+            return self.lambda(chunk);
+        }
+
+        // () is the unit value
+        if self.match_token(TokenRightParen) {
+            chunk.write_constant(Value::Unit, self.line);
+            self.last_call=None;
+            return Ok(());
+        }
+
+        // (a, b -> c) is a tuple with a, not a lambda with params a and b
+        let bare_params=std::mem::replace(&mut self.bare_params, false);
+        let res=self.grouping_items(chunk);
+        self.bare_params=bare_params;
+        res?;
+
+        // (f(a)) is a value: value >> (f(a)) calls the result
+        self.last_call=None;
+        Ok(())
+    }
+
+    // (let (IDENT expression)+ expression?) e.g 3 + (let y 2 y): the bindings are locals of the expression
+    // without a body, the value is that of the last binding
+    fn let_expression(&mut self, chunk:&mut Chunk)->Result<()> {
+        let line=self.line;
+        self.compiler.begin_scope();
+
+        let bare_params=std::mem::replace(&mut self.bare_params, false);
+        let mut last_slot;
+
+        loop {
+            let name=self.consume(TokenIdent)?;
+            self.sub_expression(chunk)?;
+            last_slot=self.compiler.add_local(name.content).expect("Let expression binding must be a local");
+
+            if !self.binding_ahead() {
+                break;
+            }
+        }
+
+        if self.check_tok(TokenRightParen) {
+            chunk.write_op(OpGetLocal(last_slot), line);
+        } else {
+            self.sub_expression(chunk)?;
+        }
+
+        self.bare_params=bare_params;
+        self.consume(TokenRightParen)?;
+
+        let count=self.compiler.end_scope();
+        chunk.write_op(OpEndScope(count, true), line);
+
+        self.last_call=None;
+        self.is_stmt=false;
+        Ok(())
+    }
+
+    /// In a let expression, whether curr starts another binding rather than the body:
+    /// the name must be followed by a token that can start an expression e.g (let x 1 y 2) but not (let x 1 y + 1).
+    /// A token that can also continue the name e.g ( [ - starts a binding only when spaced from the name
+    /// and not from what follows: (let x 1 y (x + 1) y) and (let x 1 y -1 y) but (let f g f(1)) and (let x 1 x - 1)
+    fn binding_ahead(&self)->bool {
+        if !self.check_tok(TokenIdent) {
+            return false;
+        }
+
+        let mut scanner=self.scanner.clone();
+        let spaced_before=scanner.peek().map(|ch| ch.is_ascii_whitespace()).unwrap_or(false);
+
+        let rule=match scanner.next() {
+            Some(tok) => ParseRule::get_rule(tok.token_type),
+            None => return false
+        };
+
+        if rule.prefix.is_none() {
+            return false;
+        }
+
+        let spaced_after=scanner.peek().map(|ch| ch.is_ascii_whitespace()).unwrap_or(true);
+        rule.infix.is_none() || (spaced_before && !spaced_after)
+    }
+
+    // expression ')' or expression (',' expression)* ','? ')' for a tuple: (x,) is a 1-tuple
+    fn grouping_items(&mut self, chunk:&mut Chunk)->Result<()> {
+        let line=self.line;
+        self.sub_expression(chunk)?;
+
+        if !self.match_token(TokenComma) {
+            self.consume(TokenRightParen)?;
+            return Ok(());
+        }
+
+        let mut count=1;
+        while !self.check_tok(TokenRightParen) {
+            self.with_temps(count, |parser| parser.sub_expression(chunk))?;
+            count+=1;
+
+            if !self.match_token(TokenComma) {
+                break;
+            }
+        }
+
+        self.consume(TokenRightParen)?;
+        chunk.write_op(OpTuple(count), line);
+        Ok(())
+    }
+
+    // curr should be TokenString
+    // advance so that curr is right past ending quote
+    // string literal
+    // "text {expression} text": interpolated values are converted to strings and concatenated
+    fn string(&mut self, chunk: &mut Chunk)->Result<()> {
+        let string=self.consume_one_of(vec![TokenString,TokenStringQuote])?;
+        if string.token_type==TokenStringQuote {
+            chunk.load_string(String::from(""), string.line);
+            return Ok(());
+        }
+
+        let (first, rest)=self.string_parts(string)?;
+        self.consume(TokenStringQuote)?;
+
+        // let value=Value::ObjString(content); // copies out  
+        chunk.load_string(first, string.line);
+
+        for (source, text) in rest {
+            self.with_temps(1, |parser| parser.interpolation(chunk, source, string.line))?;
+            chunk.write_op(OpToString, string.line);
+            chunk.write_op(OpAdd, string.line);
+
+            if !text.is_empty() {
+                chunk.load_string(text, string.line);
+                chunk.write_op(OpAdd, string.line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Split the content of a string token into its leading text and each interpolated expression's source
+    /// with the text following it. Escapes in the text are processed
+    fn string_parts(&self, string:Token<'src>)->Result<(String, Vec<(&'src str, String)>)> {
+        let content=string.content;
+        let mut first=None;
+        let mut rest=vec![];
+        let mut source=None;
+        let mut text=String::new();
+        let mut chars=content.char_indices();
+
+        while let Some((idx, ch)) = chars.next() {
+            match ch {
+                BACKSLASH => {
+                    let escaped=match chars.next().map(|(_, ch)| ch) {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some(ch @ (BACKSLASH | OPEN_STRING | LEFT_BRACE | RIGHT_BRACE)) => ch,
+                        Some(ch) => {
+                            let msg=format!("Unknown escape sequence '\\{}' in string", ch);
+                            return Err(self.report_msg(string, msg).unwrap_err());
+                        },
+                        None => return Err(self.report_msg(string, "Unterminated escape sequence in string").unwrap_err())
+                    };
+                    text.push(escaped);
+                },
+                LEFT_BRACE => {
+                    let end=idx+1+expr_len(&content[idx+1..]);
+                    if chars.by_ref().find(|(idx, _)| *idx==end).is_none() {
+                        return Err(self.report_msg(string, "Expected '}' after interpolated expression").unwrap_err());
+                    }
+
+                    let text=std::mem::take(&mut text);
+                    match source.replace(&content[idx+1..end]) {
+                        Some(prev) => rest.push((prev, text)),
+                        None => first=Some(text)
+                    }
+                },
+                _ => text.push(ch)
+            }
+        }
+
+        match source {
+            Some(prev) => rest.push((prev, text)),
+            None => first=Some(text)
+        }
+
+        Ok((first.unwrap_or_default(), rest))
+    }
+
+    /// Compile the source of an expression interpolated in a string with a scanner of its own
+    fn interpolation(&mut self, chunk:&mut Chunk, source:&'src str, line:usize)->Result<()> {
+        let scanner=std::mem::replace(&mut self.scanner, Scanner::new_at_line(source, line));
+        let prev_tok=self.prev_tok.take();
+        let curr_tok=self.curr_tok.take();
+
+        let res=self.interpolated_expression(chunk);
+
+        self.scanner=scanner;
+        self.prev_tok=prev_tok;
+        self.curr_tok=curr_tok;
+        self.line=curr_tok.map(|tok| tok.line).unwrap_or(line);
+        res
+    }
+
+    fn interpolated_expression(&mut self, chunk:&mut Chunk)->Result<()> {
+        self.advance()?;
+        if self.is_done() {
+            return self.report_err("Expected expression in string interpolation");
+        }
+
+        self.sub_expression(chunk)?;
+
+        if !self.is_done() {
+            return self.report_err("Expected '}' after interpolated expression");
+        }
+        Ok(())
+    }
+
+    fn literal(&mut self, chunk: &mut Chunk)->Result<()> {
+        let prev=self.expect_prev()?;
+
+        let op = match prev.token_type {
+            TokenTrue => OpTrue,
+            TokenFalse => OpFalse,
+            _ => unreachable!()
+        };
+
+        chunk.write_op(op, self.line);
+        Ok(())
+    }
+
+    // [expression (, expression)*]
+    fn list(&mut self, chunk:&mut Chunk)->Result<()> {
+        let line=self.line;
+        let mut count=0;
+
+        if !self.check_tok(TokenRightBracket) {
+            loop {
+                self.with_temps(count, |parser| parser.sub_expression(chunk))?;
+                count+=1;
+
+                if !self.match_token(TokenComma) || self.check_tok(TokenRightBracket) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenRightBracket)?;
+        chunk.write_op(OpList(count), line);
+        Ok(())
+    }
+
+    // #{ (expression : expression (, expression : expression)*)? }
+    fn map(&mut self, chunk:&mut Chunk)->Result<()> {
+        let line=self.line;
+        let mut count=0;
+
+        if !self.check_tok(TokenRightBrace) {
+            loop {
+                self.with_temps(2*count, |parser| parser.sub_expression(chunk))?;
+                self.consume(TokenColon)?;
+                self.with_temps(2*count+1, |parser| parser.sub_expression(chunk))?;
+                count+=1;
+
+                if !self.match_token(TokenComma) || self.check_tok(TokenRightBrace) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenRightBrace)?;
+        chunk.write_op(OpMap(count), line);
+        Ok(())
+    }
+
+    // infix for '.': record is already on the stack
+    fn field(&mut self, chunk:&mut Chunk, can_assign:bool)->Result<()> {
+        let name=self.consume(TokenIdent)?;
+
+        if can_assign && self.check_tok(TokenEqual) {
+            let msg=format!("Can't assign to field '{}': use with to make a record with a new value for it", name.content);
+            return self.report_msg(name, msg);
+        }
+
+        chunk.write_op(OpGetField(name.content.to_string()), name.line);
+        Ok(())
+    }
+
+    // infix for with: record is already on the stack. record with { field: value }
+    fn with(&mut self, chunk:&mut Chunk)->Result<()> {
+        let with=self.expect_prev()?;
+        self.consume(TokenLeftBrace)?;
+        let names=self.field_values(chunk, 1)?;
+        chunk.write_op(OpWith(names), with.line);
+        Ok(())
+    }
+
+    // (name : expression (, name : expression)*)? } after '{': returns the names with each value pushed in order
+    fn field_values(&mut self, chunk:&mut Chunk, temps:usize)->Result<Vec<String>> {
+        let mut names:Vec<String>=vec![];
+
+        while !self.check_tok(TokenRightBrace) {
+            let name=self.consume(TokenIdent)?;
+            if names.iter().any(|other| other==name.content) {
+                let msg=format!("Field '{}' given more than once", name.content);
+                return self.report_msg(name, msg).map(|_| vec![]);
+            }
+
+            self.consume(TokenColon)?;
+            self.with_temps(temps+names.len(), |parser| parser.sub_expression(chunk))?;
+            names.push(name.content.to_string());
+
+            if !self.match_token(TokenComma) {
+                break;
+            }
+        }
+
+        self.consume(TokenRightBrace)?;
+        Ok(names)
+    }
+
+    /// Whether '{' after name starts a record: { field: or {} after a capitalised name
+    /// so that the body of for x in xs { .. } isn't one
+    fn record_ahead(&self, name:Token<'src>)->bool {
+        if !self.check_tok(TokenLeftBrace) {
+            return false;
+        }
+
+        let mut tokens=self.scanner.clone();
+        match tokens.next().map(|tok| tok.token_type) {
+            Some(TokenIdent) => tokens.next().map(|tok| tok.token_type==TokenColon).unwrap_or(false),
+            Some(TokenRightBrace) => is_type_name(name.content),
+            _ => false
+        }
+    }
+
+    // infix for '[': list is already on the stack. xs[i] or xs[i] = value;
+    fn index(&mut self, chunk:&mut Chunk, can_assign:bool)->Result<()> {
+        let bracket=self.expect_prev()?;
+        self.with_temps(1, |parser| parser.sub_expression(chunk))?;
+        self.consume(TokenRightBracket)?;
+
+        if can_assign && self.match_token(TokenEqual) {
+            self.with_temps(2, |parser| parser.sub_expression(chunk))?;
+            self.consume(TokenSemiColon)?;
+            chunk.write_op(OpSetIndex, bracket.line);
+        } else {
+            chunk.write_op(OpIndex, bracket.line);
+        }
+        Ok(())
+    }
+
+    // infix for '(': callee is already on the stack
+    fn call(&mut self, chunk:&mut Chunk)->Result<()> {
+        let argc=self.with_temps(1, |parser| parser.argument_list(chunk))?;
+        let idx=chunk.write_op(OpCall(argc), self.line);
+        self.last_call=Some(idx);
+        Ok(())
+    }
+
+    // infix for >>: value is on the stack and becomes the first arg of the call on the right
+    // value >> f is f(value), value >> f(a) is f(value, a)
+    fn pipe(&mut self, chunk:&mut Chunk)->Result<()> {
+        let line=self.line;
+        self.with_temps(1, |parser| parser.parse_precedence(chunk, PrecPipe.get_next_prec()))?;
+
+        let call=self.last_call.filter(|idx| chunk.get_ip()==Some(*idx));
+
+        match call.and_then(|idx| chunk.get_op_mut(idx)) {
+            Some(op @ OpCall(_)) => {
+                if let OpCall(argc) = *op {
+                    *op=OpPipe(argc+1);
+                }
+            },
+            _ => {
+                chunk.write_op(OpPipe(1), line);
+            }
+        }
+
+        self.last_call=None;
+        Ok(())
+    }
+
+    // (expression (, expression)*)? ')' - returns number of args
+    fn argument_list(&mut self, chunk:&mut Chunk)->Result<usize> {
+        let mut argc=0;
+        let bare_params=std::mem::replace(&mut self.bare_params, false);
+
+        if !self.check(TokenRightParen).unwrap_or(false) {
+            loop {
+                self.with_temps(argc, |parser| parser.sub_expression(chunk))?;
+                argc+=1;
+
+                if !self.match_token(TokenComma) {
+                    break;
+                }
+            }
+        }
+
+        self.bare_params=bare_params;
+        self.consume(TokenRightParen)?;
+        Ok(argc)
+    }
+
+    // prefix for fun: anonymous function expression e.g fun (x) { x + 1 }
+    fn function_expression(&mut self, chunk:&mut Chunk)->Result<()> {
+        // name is optional and only used when printing
+        let name=if self.check(TokenIdent).unwrap_or(false) {
+            self.consume(TokenIdent)?.content
+        } else {
+            ""
+        };
+
+        self.function(chunk, name, Parser::function_body)
+    }
+
+    // lambda e.g x -> x + 1, x, y -> x + y, (x) -> { x }: prev is the first param or '('
+    fn lambda(&mut self, chunk:&mut Chunk)->Result<()> {
+        self.function(chunk, "", Parser::lambda_body)
+    }
+
+    /// True if the tokens from curr_tok are the rest of a lambda's params followed by '->'.
+    /// end is the token closing the params: TokenRightParen for (x, y) -> and TokenLambda for x, y ->
+    fn lambda_ahead(&self, end:TokenType)->bool {
+        let mut tokens=self.curr_tok.into_iter().chain(self.scanner.clone());
+
+        // () ->
+        if end==TokenRightParen && self.check_tok(TokenRightParen) {
+            tokens.next();
+            return tokens.next().map(|tok| tok.token_type==TokenLambda).unwrap_or(false);
+        }
 
+        // bare params: prev is the first param so the next token is ',' or '->'
+        if end==TokenLambda {
+            match tokens.next().map(|tok| tok.token_type) {
+                Some(TokenLambda) => return true,
+                Some(TokenComma) if self.bare_params => (),
+                _ => return false
+            }
+        }
 
-        // stmt: other statements can follow this
-        // because of this op return is not emitted so the last value remains on the stack
-        self.is_stmt=true; 
+        loop {
+            match tokens.next().map(|tok| tok.token_type) {
+                Some(TokenIdent) => (),
+                _ => return false
+            }
 
-        // // self.advance();
-        // self.expression(chunk)?;
+            match tokens.next().map(|tok| tok.token_type) {
+                Some(TokenComma) => continue,
+                Some(ty) if ty==end => break,
+                _ => return false
+            }
+        }
+
+        end==TokenLambda || tokens.next().map(|tok| tok.token_type==TokenLambda).unwrap_or(false)
+    }
 
+    /// Compile params and body into a new function with its own compiler and chunk, then load it onto the stack
+    fn function(&mut self, chunk:&mut Chunk, name:&str, body:fn(&mut Parser<'src>, &mut Chunk)->Result<usize>)->Result<()> {
+        let line=self.line;
+        let enclosing=std::mem::replace(&mut self.compiler, Compiler::new());
+        self.compiler=Compiler::new_function(enclosing);
+        let bare_params=std::mem::replace(&mut self.bare_params, true);
+        let loops=std::mem::take(&mut self.loops); // can't break out of a function
+        let mut fn_chunk=Chunk::new();
+
+        let arity=body(self, &mut fn_chunk);
+
+        let compiler=std::mem::replace(&mut self.compiler, Compiler::new());
+        let (enclosing, upvalues)=compiler.end_function();
+        self.compiler=enclosing;
+        self.bare_params=bare_params;
+        self.loops=loops;
+        self.last_call=None;
+        let arity=arity?;
+
+        let function=Function::new(name, arity, fn_chunk, upvalues);
+        let idx=chunk.add_function(function);
+        chunk.write_op(OpClosure(idx), line);
         Ok(())
     }
 
+    // '(' params ')' block - returns arity
+    fn function_body(&mut self, chunk:&mut Chunk)->Result<usize> {
+        self.compiler.begin_scope();
+        self.consume(TokenLeftParen)?;
 
-    fn expression(&mut self, chunk:&mut Chunk)->Result<()>{
-        // assign is the lowest valid precedence: other ops can bind as much as possibl
-        debug!("EXPRESSION {:?}", self);
-        // Block expression
-        if self.match_token(TokenLeftBrace) {
-            self.begin_scope(chunk)?;
-            self.block_expression(chunk)?;
-            self.end_scope(chunk)?;
-            return Ok(())
-        } 
+        let mut arity=0;
+        let mut tuple_params=vec![]; // (slot of the param, names it is destructured into)
+
+        if !self.check(TokenRightParen).unwrap_or(false) {
+            loop {
+                // (a, b): the arg is kept in a hidden local and unpacked before the body
+                if self.match_token(TokenLeftParen) {
+                    let names=self.tuple_pattern()?;
+                    let slot=self.compiler.add_local("").unwrap();
+                    tuple_params.push((slot, names));
+                } else {
+                    let param=self.consume(TokenIdent)?;
+                    self.compiler.add_local(param.content);
+                }
+                arity+=1;
 
-        if !self.is_stmt {
-            return self.report_err("Expressions not allowed immediately after another expression.")
+                if !self.match_token(TokenComma) {
+                    break;
+                }
+            }
         }
+        self.consume(TokenRightParen)?;
 
-        self.parse_precedence(chunk, PrecAssign)?;
+        for (slot, names) in tuple_params {
+            chunk.write_op(OpGetLocal(slot), self.line);
+            self.define_tuple(chunk, names, false);
+        }
 
-        // is_stmt if prev tok is semicolon - for "x=5;"
-        self.is_stmt=self.expect_prev()
-            .ok()
-            .map(|tok| tok.token_type.eq(&TokenSemiColon))
-            .unwrap_or(false);
-        Ok(())
-    }
+        if !self.check(TokenLeftBrace).unwrap_or(false) {
+            return self.report_err("Expected '{' before function body").map(|_| 0);
+        }
 
-    fn grouping(&mut self, chunk:&mut Chunk)->Result<()> {
+        // body is a block: its value is returned
+        self.is_stmt=true;
         self.expression(chunk)?;
-        // self.declaration(chunk)?;
-        self.consume(TokenRightParen)?;
-        Ok(())
+
+        if self.is_stmt {
+            chunk.write_constant(Value::Unit, self.line);
+        }
+
+        chunk.write_op(OpReturn, self.line);
+        Ok(arity)
     }
 
-    // curr should be TokenString
-    // advance so that curr is right past ending quote
-    // string literal
-    fn string(&mut self, chunk: &mut Chunk)->Result<()> {
-        let string=self.consume_one_of(vec![TokenString,TokenStringQuote])?;
-        let content=if string.token_type!=TokenStringQuote { string.content.to_string() } else { String::from("") };
+    // params '->' expression - returns arity
+    fn lambda_body(&mut self, chunk:&mut Chunk)->Result<usize> {
+        self.compiler.begin_scope();
+        let first=self.expect_prev()?;
 
-        // let value=Value::ObjString(content); // copies out  
-        chunk.load_string(content, string.line);
+        let mut arity=0;
+        if first.token_type==TokenIdent {
+            self.compiler.add_local(first.content);
+            arity+=1;
 
-        if string.token_type!=TokenStringQuote {
-            self.consume(TokenStringQuote)?;
+            while self.match_token(TokenComma) {
+                let param=self.consume(TokenIdent)?;
+                self.compiler.add_local(param.content);
+                arity+=1;
+            }
+        } else if !self.match_token(TokenRightParen) {
+            loop {
+                let param=self.consume(TokenIdent)?;
+                self.compiler.add_local(param.content);
+                arity+=1;
+
+                if !self.match_token(TokenComma) {
+                    break;
+                }
+            }
+            self.consume(TokenRightParen)?;
         }
-        Ok(())
-    }
+        self.consume(TokenLambda)?;
 
-    fn literal(&mut self, chunk: &mut Chunk)->Result<()> {
-        let prev=self.expect_prev()?;
+        // body is any expression, a block included: its value is returned
+        self.sub_expression(chunk)?;
 
-        let op = match prev.token_type {
-            TokenTrue => OpTrue,
-            TokenFalse => OpFalse,
-            _ => unreachable!()
-        };
+        if self.is_stmt {
+            chunk.write_constant(Value::Unit, self.line);
+        }
 
-        chunk.write_op(op, self.line);
-        Ok(())
+        chunk.write_op(OpReturn, self.line);
+        Ok(arity)
     }
 
     // call based on enum
@@ -244,7 +1373,21 @@ impl<'src> Parser<'src> {
             ParseGrouping => self.grouping(chunk),
             ParseString => self.string(chunk),
             ParseIdent => self.parse_ident(chunk, can_assign),
-            ParseLiteral => self.literal(chunk)
+            ParseLiteral => self.literal(chunk),
+            ParseAnd => self.and(chunk),
+            ParseOr => self.or(chunk),
+            ParseCall => self.call(chunk),
+            ParseFunction => self.function_expression(chunk),
+            ParsePipe => self.pipe(chunk),
+            ParseInfix => self.infix_call(chunk),
+            ParseWhile => self.while_loop(chunk),
+            ParseFor => self.for_loop(chunk),
+            ParseList => self.list(chunk),
+            ParseIndex => self.index(chunk, can_assign),
+            ParseMap => self.map(chunk),
+            ParseMatch => self.match_expression(chunk),
+            ParseDot => self.field(chunk, can_assign),
+            ParseWith => self.with(chunk)
         }
     }
 
@@ -270,15 +1413,35 @@ impl<'src> Parser<'src> {
 
         self.call_parse_fn(chunk, prefix_fn, can_assign)?;
 
+        // literals and arithmetic are never functions, so a '(' after them isn't a call
+        // e.g (let x 20 (x + 30)) binds x to 20
+        let mut callable=!matches!(prefix_fn, ParseNumber | ParseString | ParseLiteral);
+
         // infix down here - pratt parsing
         loop {
             if self.is_done() {
                 break;
             }
 
+            // assignment consumed its ';': what follows is a new expression e.g x=1; (x)
+            if self.expect_prev()?.token_type==TokenSemiColon {
+                break;
+            }
+
             let curr_tok=self.expect_current()?;
+
+            if curr_tok.token_type==TokenLeftParen && !callable {
+                break;
+            }
             // let rule=self.expect_rule(curr_tok)?;
-            let rule=ParseRule::get_rule(curr_tok.token_type);
+            let mut rule=ParseRule::get_rule(curr_tok.token_type);
+
+            // $name: precedence comes from the fixity declared for name
+            if curr_tok.token_type==TokenInfix {
+                if let Some(name) = self.peek_next_token() {
+                    rule.prec=self.fixity(name).prec;
+                }
+            }
             
             // when rule.prec is PrecNone this will break - RightParen breaks before advance
             if prec.get_precedence_val() > rule.prec.get_precedence_val() {
@@ -300,6 +1463,7 @@ impl<'src> Parser<'src> {
 
             let infix=infix.unwrap();
             self.call_parse_fn(chunk, infix, can_assign)?;
+            callable=matches!(infix, ParseCall | ParseIndex | ParsePipe | ParseInfix | ParseAnd | ParseOr | ParseDot);
         }
 
 
@@ -347,6 +1511,16 @@ impl<'src> Parser<'src> {
         Ok(())
     }
 
+    /// Token after curr_tok without advancing
+    fn peek_next_token(&self)->Option<Token<'src>> {
+        self.scanner.clone().next()
+    }
+
+    /// Return true if current_tok is ty, false if not or empty
+    fn check_tok(&self, ty:TokenType)->bool {
+        self.curr_tok.map(|t| t.token_type==ty).unwrap_or(false)
+    }
+
     /// Return true if current_tok is ty else false. None if empty
     fn check(&mut self, ty:TokenType)->Option<bool> {
         self.curr_tok.map(|t| t.token_type==ty)
@@ -423,43 +1597,65 @@ impl<'src> Parser<'src> {
         let ident=self.expect_prev()?;
         self.expect_token_type(ident, TokenIdent, "identifier")?;
 
-        // use hash to get value instead of full string (less work at runtime)
-        let ident_content=ident.content.to_string();
-
+        // x -> body
+        if self.lambda_ahead(TokenLambda) {
+            return self.lambda(chunk);
+        }
 
         // set get_op here
-        let mut get_op:Inst;
+        let get_op=self.resolve_variable(ident.content);
 
-        let try_local=self.compiler.resolve_local(ident.content);
-
-        if let Some(idx) = try_local {
-            get_op=OpGetLocal(idx);
-        } else {
-            get_op=OpGetGlobal(ident_content.clone());
+        // Name { field: value } makes a record of the type Name
+        if self.record_ahead(ident) {
+            chunk.write_op(get_op, ident.line);
+            self.advance()?;
+            let names=self.field_values(chunk, 1)?;
+            chunk.write_op(OpRecord(names), ident.line);
+            return Ok(());
         }
 
-        // Set var here
-        if self.match_token(TokenEqual) {
+        // Set var here: x op= value is x = x op value
+        let compound=self.match_compound_assign()?;
+        if compound.is_some() || self.match_token(TokenEqual) {
             if !can_assign {
                 let msg=format!("Can't assign to {}", ident.content);
                 self.report_msg(ident, msg)?;
             }
 
-            self.expression(chunk)?; // assign to expression
-            self.consume(TokenSemiColon)?;
-
-            // declareVariable() here - if global do nothing. else, add local with ident
-            let local_added=self.compiler.add_local(ident.content);
+            // locals are checked here, globals when the assignment runs
+            if self.compiler.is_mutable(ident.content)==Some(false) {
+                let msg=format!("Cannot assign to immutable variable '{}': declare it with let mut", ident.content);
+                self.report_msg(ident, msg)?;
+            }
 
-            // local_added: idx where loc was added      
-            let mut set_op:Inst;
-            if let Some(idx) = local_added {
-                set_op=OpSetLocal(idx);
-            } else {
-                set_op=OpSetGlobal(ident_content);
+            match compound {
+                Some(op) => {
+                    // a global is checked before the read so that x += 1 fails like x = 1
+                    let get_op=match self.resolve_variable(ident.content) {
+                        OpGetGlobal(name) => OpGetAssignGlobal(name),
+                        get_op => get_op
+                    };
+                    chunk.write_op(get_op, ident.line);
+                    self.with_temps(1, |parser| parser.expression(chunk))?;
+                    chunk.write_op(op, ident.line);
+                },
+                None => self.expression(chunk)? // assign to expression
             }
+            self.consume(TokenSemiColon)?;
 
-            chunk.write_op(set_op, ident.line);
+            // update the variable in place: only let declares a new one
+            match get_op {
+                OpGetLocal(idx) => {
+                    chunk.write_op(OpSetLocal(idx), ident.line);
+                    chunk.write_op(OpPop, ident.line);
+                },
+                OpGetUpvalue(idx) => {
+                    chunk.write_op(OpSetUpvalue(idx), ident.line);
+                },
+                _ => {
+                    chunk.write_op(OpAssignGlobal(ident.content.to_string()), ident.line);
+                }
+            }
 
         // Get var here
         } else {    
@@ -469,12 +1665,299 @@ impl<'src> Parser<'src> {
         Ok(())
     }
 
+    /// Arithmetic op of +=, -=, *= or /= if it is the current token
+    fn match_compound_assign(&mut self)->Result<Option<Inst>> {
+        let op=match self.curr_tok.map(|tok| tok.token_type) {
+            Some(TokenPlusEq) => OpAdd,
+            Some(TokenMinusEq) => OpSub,
+            Some(TokenStarEq) => OpMul,
+            Some(TokenSlashEq) => OpDiv,
+            _ => return Ok(None)
+        };
+
+        self.advance()?;
+        Ok(Some(op))
+    }
+
+    /// Op to get the variable called name: local, then captured from an enclosing function, then global
+    fn resolve_variable(&mut self, name:&str)->Inst {
+        if let Some(idx) = self.compiler.resolve_local(name) {
+            OpGetLocal(idx)
+        } else if let Some(idx) = self.compiler.resolve_upvalue(name) {
+            OpGetUpvalue(idx)
+        } else {
+            OpGetGlobal(name.to_string())
+        }
+    }
+
+    /// Bind ident to the value on top of the stack.
+    /// declareVariable() here - if global set it. else, add local with ident
+    fn define_variable(&mut self, chunk: &mut Chunk, ident:Token<'src>, mutable:bool) {
+        let local_added=self.compiler.add_local_as(ident.content, mutable);
+
+        // local_added: idx where loc was added      
+        match local_added {
+            Some(idx) => {
+                chunk.write_op(OpSetLocal(idx), ident.line);
+            },
+            None => self.set_global(chunk, ident, mutable)
+        }
+    }
+
+    // declared with export: importers can access the global as well
+    fn set_global(&self, chunk: &mut Chunk, ident:Token<'src>, mutable:bool) {
+        chunk.write_op(OpSetGlobal(ident.content.to_string(), mutable), ident.line);
+
+        if self.exporting {
+            chunk.write_op(OpExport(ident.content.to_string()), ident.line);
+        }
+    }
+
+    // ident (',' ident)* ','? ')' - the names bound by destructuring a tuple, after '('
+    fn tuple_pattern(&mut self)->Result<Vec<Token<'src>>> {
+        let mut names=vec![];
+
+        while !self.check_tok(TokenRightParen) {
+            names.push(self.consume(TokenIdent)?);
+
+            if !self.match_token(TokenComma) {
+                break;
+            }
+        }
+
+        self.consume(TokenRightParen)?;
+        Ok(names)
+    }
+
+    // tuple on top of the stack is unpacked into one variable per name
+    fn define_tuple(&mut self, chunk: &mut Chunk, names:Vec<Token<'src>>, mutable:bool) {
+        chunk.write_op(OpUnpack(names.len()), self.line);
+
+        if self.compiler.depth()==0 {
+            // each set pops the top item, which belongs to the last name
+            for name in names.iter().rev() {
+                self.set_global(chunk, *name, mutable);
+            }
+        } else {
+            // items are already on the stack in the order of their slots
+            for name in names {
+                self.compiler.add_local_as(name.content, mutable);
+            }
+        }
+    }
+
     /// Grammar functions
+
+    // fun name(params) { body }
+    fn fun_declaration(&mut self, chunk: &mut Chunk)->Result<()> {
+        let name=self.consume(TokenIdent)?;
+
+        // local is declared before the body so that the function can refer to itself
+        let local_added=self.compiler.add_local(name.content);
+        self.function(chunk, name.content, Parser::function_body)?;
+
+        match local_added {
+            Some(idx) => {
+                chunk.write_op(OpSetLocal(idx), name.line);
+            },
+            None => self.set_global(chunk, name, false)
+        }
+
+        self.match_token(TokenSemiColon);
+        self.is_stmt=true;
+        Ok(())
+    }
+
+    // type Name { field (, field)* } - binds Name to the record type
+    // or type Name = Variant (( field (, field)* ))? (| Variant ...)* ;? - binds each variant
+    fn type_declaration(&mut self, chunk: &mut Chunk)->Result<()> {
+        let name=self.consume(TokenIdent)?;
+        self.is_stmt=true;
+
+        if self.match_token(TokenEqual) {
+            return self.sum_type(chunk, name);
+        }
+
+        self.consume(TokenLeftBrace)?;
+        let fields=self.field_names(name, TokenRightBrace)?;
+
+        let idx=chunk.add_type(RecordType::new(name.content, fields));
+        chunk.write_op(OpType(idx), name.line);
+        self.type_names.insert(name.content.to_string());
+        self.define_variable(chunk, name, false);
+        Ok(())
+    }
+
+    // after '=': a variant with fields is bound to its type, which is called to make one e.g Circle(2).
+    // One without fields is bound to its only value
+    fn sum_type(&mut self, chunk: &mut Chunk, name:Token<'src>)->Result<()> {
+        let mut variants:Vec<&str>=vec![];
+
+        loop {
+            let variant=self.consume(TokenIdent)?;
+            if variants.contains(&variant.content) {
+                let msg=format!("Duplicate variant '{}' in type {}", variant.content, name.content);
+                return self.report_msg(variant, msg);
+            }
+            variants.push(variant.content);
+
+            let fields=if self.match_token(TokenLeftParen) { self.field_names(variant, TokenRightParen)? } else { vec![] };
+            let is_constant=fields.is_empty();
+
+            let idx=chunk.add_type(RecordType::variant(variant.content, fields, name.content));
+            chunk.write_op(OpType(idx), variant.line);
+            self.type_names.insert(variant.content.to_string());
+            if is_constant {
+                chunk.write_op(OpCall(0), variant.line);
+            }
+            self.define_variable(chunk, variant, false);
+
+            if !self.match_token(TokenBar) {
+                break;
+            }
+        }
+
+        self.match_token(TokenSemiColon);
+        Ok(())
+    }
+
+    // field (, field)* ,? close - names of the fields of the type called owner
+    fn field_names(&mut self, owner:Token<'src>, close:TokenType)->Result<Vec<String>> {
+        let mut fields:Vec<String>=vec![];
+
+        while !self.check_tok(close) {
+            let field=self.consume(TokenIdent)?;
+            if fields.iter().any(|other| other==field.content) {
+                let msg=format!("Duplicate field '{}' in type {}", field.content, owner.content);
+                return self.report_msg(field, msg).map(|_| vec![]);
+            }
+            fields.push(field.content.to_string());
+
+            if !self.match_token(TokenComma) {
+                break;
+            }
+        }
+
+        self.consume(close)?;
+        Ok(fields)
+    }
+
+    // import "path/name" (as alias)? ;? or import name (as alias)? ;? - binds the module, by default to the last part of its path
+    fn import_statement(&mut self, chunk: &mut Chunk)->Result<()> {
+        let path=if self.match_token(TokenStringQuote) {
+            let path=self.consume(TokenString)?;
+            self.consume(TokenStringQuote)?;
+            path
+        } else {
+            self.consume(TokenIdent)?
+        };
+
+        let name=if self.match_token(TokenAs) {
+            self.consume(TokenIdent)?
+        } else {
+            let content=path.content.rsplit('/').next().unwrap_or_default();
+            let content=content.strip_suffix(".txt").unwrap_or(content);
+
+            let is_ident=content.chars().next().is_some_and(|c| c.is_alphabetic() || c=='_')
+                && content.chars().all(|c| c.is_alphanumeric() || c=='_');
+            if !is_ident {
+                let msg=format!("Module name '{}' is not an identifier: import it with as", content);
+                return self.report_msg(path, msg);
+            }
+            Token { token_type: TokenIdent, content, line: path.line }
+        };
+
+        chunk.write_op(OpImport(path.content.to_string()), path.line);
+        self.define_variable(chunk, name, false);
+
+        self.match_token(TokenSemiColon);
+        self.is_stmt=true;
+        Ok(())
+    }
+
+    // export (let | fun | type) ... - importers can access the globals the declaration binds
+    fn export_declaration(&mut self, chunk: &mut Chunk)->Result<()> {
+        let export=self.expect_prev()?;
+
+        if self.compiler.function_type()!=FunctionType::Script || self.compiler.depth() > 0 {
+            return self.report_msg(export, "Can only export declarations at the top level");
+        }
+
+        let is_declaration=self.check_tok(TokenLet) || self.check_tok(TokenTypeDecl)
+            || (self.check_tok(TokenFunc) && self.peek_next_token().map(|tok| tok.token_type==TokenIdent).unwrap_or(false));
+        if !is_declaration {
+            return self.report_msg(export, "Expected a let, fun or type declaration after export");
+        }
+
+        self.exporting=true;
+        let res=self.declaration(chunk, false);
+        self.exporting=false;
+        res
+    }
+
+    // (infixl | infixr) level '$' name ';' - level 1 binds loosest (like or) and 6 tightest (like *)
+    fn fixity_declaration(&mut self)->Result<()> {
+        let assoc=self.expect_prev()?;
+        let level=self.consume(TokenInteger)?;
+
+        // 1 binds like or, 6 like +
+        let levels=[PrecOr, PrecAnd, PrecEq, PrecComp, PrecRange, PrecTerm];
+        let prec=match level.content.parse::<usize>() {
+            Ok(n @ 1..=6) => levels[n-1],
+            _ => return self.report_msg(level, "Infix precedence must be between 1 and 6")
+        };
+
+        self.consume(TokenInfix)?;
+        let name=self.consume(TokenIdent)?;
+        self.consume(TokenSemiColon)?;
+
+        let fixity=Fixity { prec, is_right: assoc.token_type==TokenInfixr };
+        self.fixities.insert(name.content.to_string(), fixity);
+        Ok(())
+    }
+
+    // return expression? ';'
+    fn return_statement(&mut self, chunk: &mut Chunk)->Result<()> {
+        let ret=self.expect_prev()?;
+        if self.compiler.function_type()==FunctionType::Script {
+            return self.report_msg(ret, "Can't return from top-level code");
+        }
+
+        if self.check(TokenSemiColon).unwrap_or(false) {
+            chunk.write_constant(Value::Unit, ret.line);
+        } else {
+            self.sub_expression(chunk)?;
+        }
+
+        self.consume(TokenSemiColon)?;
+        chunk.write_op(OpReturn, ret.line);
+        Ok(())
+    }
     
-    // let x=2;
+    // let x=2; or let mut x=2; for one that can be assigned to
     // varDeclaration
     fn let_declaration(&mut self, chunk: &mut Chunk)->Result<()>  {
-        self.parse_precedence(chunk, PrecAssign)?;
+        let mutable=self.match_token(TokenMut);
+
+        // let (a, b) = pair;
+        if self.match_token(TokenLeftParen) {
+            let names=self.tuple_pattern()?;
+            self.consume(TokenEqual)?;
+
+            self.expression(chunk)?;
+            self.consume(TokenSemiColon)?;
+
+            self.define_tuple(chunk, names, mutable);
+            return Ok(());
+        }
+
+        let ident=self.consume(TokenIdent)?;
+        self.consume(TokenEqual)?;
+
+        self.expression(chunk)?;
+        self.consume(TokenSemiColon)?;
+
+        self.define_variable(chunk, ident, mutable);
         Ok(())
     }
 
@@ -530,8 +2013,43 @@ impl<'src> Parser<'src> {
         } else if self.match_token(TokenIf) {
             self.if_expression(chunk)?;
             return Ok(())
+        } else if self.match_token(TokenWhile) {
+            self.while_loop(chunk)?;
+            self.loop_statement(chunk)?;
+        } else if self.match_token(TokenFor) {
+            self.for_loop(chunk)?;
+            self.loop_statement(chunk)?;
+        } else if self.match_token(TokenBreak) {
+            self.break_statement(chunk)?;
+        } else if self.match_token(TokenContinue) {
+            self.continue_statement(chunk)?;
+        } else if self.check_tok(TokenMatch) {
+            // like a loop but a match ending the input is its value
+            self.expression(chunk)?;
+            if self.curr_tok.is_some() {
+                self.loop_statement(chunk)?;
+            }
+        } else if self.check(TokenFunc).unwrap_or(false) && self.peek_next_token().map(|tok| tok.token_type==TokenIdent).unwrap_or(false) {
+            self.advance()?;
+            self.fun_declaration(chunk)?;
+        } else if self.match_token(TokenTypeDecl) {
+            self.type_declaration(chunk)?;
+        } else if self.match_token(TokenImport) {
+            self.import_statement(chunk)?;
+        } else if self.match_token(TokenExport) {
+            self.export_declaration(chunk)?;
+        } else if self.match_token(TokenReturn) {
+            self.return_statement(chunk)?;
+        } else if self.match_token(TokenInfixl) || self.match_token(TokenInfixr) {
+            self.fixity_declaration()?;
         } else {
             self.expression(chunk)?;
+
+            // expression statement e.g f(x); discards the value
+            if !self.is_stmt && self.match_token(TokenSemiColon) {
+                chunk.write_op(OpPop, self.line);
+                self.is_stmt=true;
+            }
         }
 
         // put expression and block together
@@ -543,11 +2061,7 @@ impl<'src> Parser<'src> {
     pub fn compile(&mut self, chunk: &mut Chunk)->Result<()> {
         // at first: only exprs
 
-        self.advance()?;
-
-        while let Some(_) = self.curr_tok {
-            self.declaration(chunk, false)?;
-        }
+        self.declarations(chunk)?;
 
         debug!("After finishing: is_stmt {}", self.is_stmt);
 
@@ -556,13 +2070,31 @@ impl<'src> Parser<'src> {
             self.end_compile(chunk);
         }
 
+        Ok(())
+
+        // consume(EOF, expect end of expr)
+        // Ok(())
+    }
+
+    /// Compile an imported file: instead of returning a value it ends by handing its module to the importer
+    pub fn compile_module(&mut self, chunk: &mut Chunk)->Result<()> {
+        self.declarations(chunk)?;
+        chunk.write_op(OpEndModule, self.line);
+        Ok(())
+    }
+
+    // every declaration up to the end of the source
+    fn declarations(&mut self, chunk: &mut Chunk)->Result<()> {
+        self.advance()?;
+
+        while let Some(_) = self.curr_tok {
+            self.declaration(chunk, false)?;
+        }
+
         match self.delim_scanner.end() {
             Err(delim_err) => self.report_err(delim_err),
             _ => Ok(())
         }
-
-        // consume(EOF, expect end of expr)
-        // Ok(())
     }
 
     pub fn end_compile(&mut self, chunk:&mut Chunk) {
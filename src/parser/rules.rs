@@ -3,17 +3,24 @@ use crate::scanner::tokens::*;
 
 // ParseRule, Precedence
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy)]
 pub enum Precedence {
     PrecNone,
     PrecAssign, // = (lowest valid)
+    PrecPipe, // >>
     PrecOr,
     PrecAnd,
     PrecEq, // ==, !=
     PrecComp, // lt,gt, lte, gte
+    PrecRange, // ..
+    PrecBitOr, // |
+    PrecBitXor, // ^
+    PrecBitAnd, // &
+    PrecShift, // <<, >>>
     PrecTerm, // + -
-    PrecFactor, // *, /
+    PrecFactor, // *, /, %
     PrecUnary, // !, - e.g -2, !false
+    PrecPower, // ** binds tighter than unary on its left: -2 ** 2 is -(2 ** 2)
     PrecCall, // () -> for calling a function
     PrecPrimary // (expression), number, string, ident, etc
 }
@@ -24,15 +31,22 @@ impl Precedence {
         match self {
             PrecNone => 1,
             PrecAssign => 2,
-            PrecOr => 3,
-            PrecAnd => 4,
-            PrecEq => 5,
-            PrecComp => 6,
-            PrecTerm => 7,
-            PrecFactor => 8,
-            PrecUnary => 9,
-            PrecCall => 10,
-            PrecPrimary => 11
+            PrecPipe => 3,
+            PrecOr => 4,
+            PrecAnd => 5,
+            PrecEq => 6,
+            PrecComp => 7,
+            PrecRange => 8,
+            PrecBitOr => 9,
+            PrecBitXor => 10,
+            PrecBitAnd => 11,
+            PrecShift => 12,
+            PrecTerm => 13,
+            PrecFactor => 14,
+            PrecUnary => 15,
+            PrecPower => 16,
+            PrecCall => 17,
+            PrecPrimary => 18
         }
     }
 
@@ -41,16 +55,23 @@ impl Precedence {
         match val {
             1 => PrecNone,
             2 => PrecAssign,
-            3 => PrecOr,
-            4 => PrecAnd,
-            5 => PrecEq,
-            6 => PrecComp,
-            7 => PrecTerm,
-            8 => PrecFactor,
-            9 => PrecUnary,
-            10 => PrecCall,
-            11 => PrecPrimary,
-            val if val > 11 => PrecPrimary,
+            3 => PrecPipe,
+            4 => PrecOr,
+            5 => PrecAnd,
+            6 => PrecEq,
+            7 => PrecComp,
+            8 => PrecRange,
+            9 => PrecBitOr,
+            10 => PrecBitXor,
+            11 => PrecBitAnd,
+            12 => PrecShift,
+            13 => PrecTerm,
+            14 => PrecFactor,
+            15 => PrecUnary,
+            16 => PrecPower,
+            17 => PrecCall,
+            18 => PrecPrimary,
+            val if val > 18 => PrecPrimary,
             _ => unreachable!()
         }
     }
@@ -88,7 +109,21 @@ pub enum ParseFn {
     ParseGrouping,
     ParseString,
     ParseIdent,
-    ParseLiteral // true,false
+    ParseLiteral, // true,false
+    ParseAnd,
+    ParseOr,
+    ParseCall,
+    ParseFunction,
+    ParsePipe,
+    ParseInfix,
+    ParseWhile,
+    ParseFor,
+    ParseList,
+    ParseIndex,
+    ParseMap,
+    ParseMatch,
+    ParseDot, // record.field
+    ParseWith // record with { field: value }
 }
 
 pub use ParseFn::*;
@@ -116,16 +151,43 @@ impl ParseRule {
     pub fn get_rule(ty:TokenType)->ParseRule{
         match ty {
             TokenInteger => ParseRule::new(Some(ParseNumber), None, PrecNone),
+            TokenFloat => ParseRule::new(Some(ParseNumber), None, PrecNone),
             TokenMinus => ParseRule::new(Some(ParseUnary), Some(ParseBinary), PrecTerm),
             TokenPlus => ParseRule::new(None, Some(ParseBinary), PrecTerm),
             TokenStar => ParseRule::new(None, Some(ParseBinary), PrecFactor),
             TokenSlash => ParseRule::new(None, Some(ParseBinary), PrecFactor),
-            TokenLeftParen => ParseRule::new(Some(ParseGrouping), None, PrecNone),
+            TokenPercent => ParseRule::new(None, Some(ParseBinary), PrecFactor),
+            TokenStarStar => ParseRule::new(None, Some(ParseBinary), PrecPower),
+            TokenAmpersand => ParseRule::new(None, Some(ParseBinary), PrecBitAnd),
+            TokenBar => ParseRule::new(None, Some(ParseBinary), PrecBitOr),
+            TokenCaret => ParseRule::new(None, Some(ParseBinary), PrecBitXor),
+            TokenShiftLeft => ParseRule::new(None, Some(ParseBinary), PrecShift),
+            TokenShiftRight => ParseRule::new(None, Some(ParseBinary), PrecShift),
+            TokenLeftParen => ParseRule::new(Some(ParseGrouping), Some(ParseCall), PrecCall),
+            TokenLeftBracket => ParseRule::new(Some(ParseList), Some(ParseIndex), PrecCall),
+            TokenMapOpen => ParseRule::new(Some(ParseMap), None, PrecNone),
+            TokenFunc => ParseRule::new(Some(ParseFunction), None, PrecNone),
+            TokenWhile => ParseRule::new(Some(ParseWhile), None, PrecNone),
+            TokenFor => ParseRule::new(Some(ParseFor), None, PrecNone),
+            TokenMatch => ParseRule::new(Some(ParseMatch), None, PrecNone),
             TokenStringQuote => ParseRule::new(Some(ParseString), None, PrecNone),
             TokenIdent => ParseRule::new(Some(ParseIdent), None, PrecNone),
             TokenTrue => ParseRule::new(Some(ParseLiteral), None, PrecNone),
             TokenFalse => ParseRule::new(Some(ParseLiteral), None, PrecNone),
             TokenNot => ParseRule::new(Some(ParseUnary), None, PrecNone),
+            TokenAnd => ParseRule::new(None, Some(ParseAnd), PrecAnd),
+            TokenOr => ParseRule::new(None, Some(ParseOr), PrecOr),
+            TokenPipe => ParseRule::new(None, Some(ParsePipe), PrecPipe),
+            TokenInfix => ParseRule::new(None, Some(ParseInfix), PrecFactor), // default for $name without a fixity declaration
+            TokenEqEq => ParseRule::new(None, Some(ParseBinary), PrecEq),
+            TokenNotEq => ParseRule::new(None, Some(ParseBinary), PrecEq),
+            TokenLess => ParseRule::new(None, Some(ParseBinary), PrecComp),
+            TokenLessEq => ParseRule::new(None, Some(ParseBinary), PrecComp),
+            TokenGt => ParseRule::new(None, Some(ParseBinary), PrecComp),
+            TokenGtEq => ParseRule::new(None, Some(ParseBinary), PrecComp),
+            TokenRange => ParseRule::new(None, Some(ParseBinary), PrecRange),
+            TokenDot => ParseRule::new(None, Some(ParseDot), PrecCall),
+            TokenWith => ParseRule::new(None, Some(ParseWith), PrecCall),
             _ => ParseRule::new(None, None, PrecNone)
         }
     }
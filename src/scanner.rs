@@ -5,7 +5,7 @@ pub mod delim;
 
 use tokens::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Scanner<'src> {
     source:&'src str,
     chars:LookaheadChars<'src>,
@@ -20,10 +20,58 @@ fn is_valid_ident_char(char:char)->bool {
     char.is_alphanumeric() || char==UNDERSCORE
 }
 
+/// Byte length of the string content source starts with: up to its closing quote or the end.
+/// Escaped chars are skipped and a quote inside an interpolated {expression} starts a nested string
+pub fn string_len(source:&str)->usize {
+    let mut chars=source.char_indices();
+
+    while let Some((idx, ch)) = chars.next() {
+        match ch {
+            OPEN_STRING => return idx,
+            BACKSLASH => {
+                chars.next();
+            },
+            LEFT_BRACE => {
+                let end=idx+1+expr_len(&source[idx+1..]);
+                chars.by_ref().find(|(idx, _)| *idx==end);
+            },
+            _ => ()
+        }
+    }
+
+    source.len()
+}
+
+/// Byte length of the interpolated expression source starts with: up to its closing brace or the end
+pub fn expr_len(source:&str)->usize {
+    let mut chars=source.char_indices();
+    let mut depth=0;
+
+    while let Some((idx, ch)) = chars.next() {
+        match ch {
+            RIGHT_BRACE if depth==0 => return idx,
+            RIGHT_BRACE => depth-=1,
+            LEFT_BRACE => depth+=1,
+            OPEN_STRING => {
+                let end=idx+1+string_len(&source[idx+1..]);
+                chars.by_ref().find(|(idx, _)| *idx==end);
+            },
+            _ => ()
+        }
+    }
+
+    source.len()
+}
+
 impl<'src> Scanner<'src> {
     pub fn new<'source>(source:&'source str)->Scanner<'source>{
+        Scanner::new_at_line(source, 1)
+    }
+
+    /// Scanner for source that starts at line e.g an expression interpolated in a string
+    pub fn new_at_line<'source>(source:&'source str, line:usize)->Scanner<'source>{
         let chars=LookaheadChars::new(source);
-        Scanner { source, chars, start: 0, current: 0, line: 1, is_string:false }
+        Scanner { source, chars, start: 0, current: 0, line, is_string:false }
     }
 
     pub fn peek(&mut self)->Option<char> {
@@ -37,8 +85,10 @@ impl<'src> Scanner<'src> {
     // increment, return next char (as &str)
         // str slice of next k chars (k=1)
     fn advance(&mut self)->Option<char>{
-        self.current+=1;
-        self.chars.next()
+        let char=self.chars.next();
+        // current is a byte idx so that lexemes can be sliced from source
+        self.current+=char.map(|ch| ch.len_utf8()).unwrap_or(1);
+        char
     }
 
     // advance iterator while pred(char) true
@@ -66,8 +116,11 @@ impl<'src> Scanner<'src> {
 
         let mut float=false;
 
+        // 0..10 is a range: only a digit after the dot makes a float
+        let is_digit_next=self.peek_next().map(|ch| ch.is_ascii_digit()).unwrap_or(false);
+
         if let Some(pk) = self.peek() {
-            if pk==DOT {
+            if pk==DOT && is_digit_next {
                 self.advance();
                 self.advance_while(|char| char.is_ascii_digit());
                 float=true;
@@ -93,7 +146,11 @@ impl<'src> Scanner<'src> {
         }
 
         // self.start=self.current; // idx already at first char of string
-        self.advance_while(|ch| ch!=OPEN_STRING);
+        // escapes and interpolation are kept as written for the parser
+        let end=self.current+string_len(&self.source[self.current..]);
+        while self.current < end {
+            self.advance();
+        }
 
         // let tok=self.make_token(TokenString); // start=idx of first char, curr=idx of terminator
 
@@ -318,6 +375,10 @@ fn test_string() {
     let mut s=Scanner::new(inp);
     assert_eq!(s.serialize(),  "[TokenLeftParen('('),TokenInteger('2'),TokenPlus('+'),TokenInteger('2'),TokenRightParen(')'),TokenStringQuote('\"'),TokenString('  (2+3+4)   \n\t   5+6 '),TokenStringQuote('\"'),TokenInteger('234'),TokenPlus('+'),TokenInteger('56')]");
 
+    let inp="\"a \\\" {\"b\"} \\{\" 1";
+    let mut s=Scanner::new(inp);
+    assert_eq!(s.serialize(), "[TokenStringQuote('\"'),TokenString('a \\\" {\"b\"} \\{'),TokenStringQuote('\"'),TokenInteger('1')]");
+
     let inp="\" \", 23, \" \"";
     let mut s=Scanner::new(inp);
     assert_eq!(s.serialize(),  "[TokenStringQuote('\"'),TokenString(' '),TokenStringQuote('\"'),TokenComma(','),TokenInteger('23'),TokenComma(','),TokenStringQuote('\"'),TokenString(' '),TokenStringQuote('\"')]");
@@ -360,7 +421,11 @@ fn test_ident() {
 
     let inp="falsefun fun funfalse for!x";
     let mut s=Scanner::new(inp);
-    assert_eq!(s.serialize(),"[TokenIdent('falsefun'),TokenFunc('fun'),TokenIdent('funfalse'),TokenIdent('for'),TokenNot('!'),TokenIdent('x')]");
+    assert_eq!(s.serialize(),"[TokenIdent('falsefun'),TokenFunc('fun'),TokenIdent('funfalse'),TokenFor('for'),TokenNot('!'),TokenIdent('x')]");
+
+    let inp="for i in 0..10 { } 1.5 2.";
+    let mut s=Scanner::new(inp);
+    assert_eq!(s.serialize(),"[TokenFor('for'),TokenIdent('i'),TokenIn('in'),TokenInteger('0'),TokenRange('..'),TokenInteger('10'),TokenLeftBrace('{'),TokenRightBrace('}'),TokenFloat('1.5'),TokenInteger('2'),TokenDot('.')]");
 }
 
 #[test]
@@ -376,6 +441,34 @@ pub fn test_many() {
     let code="(x $ y + map >> succ)";
     let mut s=Scanner::new(code);
     assert_eq!(s.serialize(), "[TokenLeftParen('('),TokenIdent('x'),TokenInfix('$'),TokenIdent('y'),TokenPlus('+'),TokenIdent('map'),TokenPipe('>>'),TokenIdent('succ'),TokenRightParen(')')]");
+
+    let code="infixr 5 $max; infixlr";
+    let mut s=Scanner::new(code);
+    assert_eq!(s.serialize(), "[TokenInfixr('infixr'),TokenInteger('5'),TokenInfix('$'),TokenIdent('max'),TokenSemiColon(';'),TokenIdent('infixlr')]");
+
+    let code="a % b ** c & d | e ^ f << g >>> h >> i";
+    let mut s=Scanner::new(code);
+    assert_eq!(s.serialize(), "[TokenIdent('a'),TokenPercent('%'),TokenIdent('b'),TokenStarStar('**'),TokenIdent('c'),TokenAmpersand('&'),TokenIdent('d'),TokenBar('|'),TokenIdent('e'),TokenCaret('^'),TokenIdent('f'),TokenShiftLeft('<<'),TokenIdent('g'),TokenShiftRight('>>>'),TokenIdent('h'),TokenPipe('>>'),TokenIdent('i')]");
+
+    let code="type Point { x, y } p.x with typed";
+    let mut s=Scanner::new(code);
+    assert_eq!(s.serialize(), "[TokenTypeDecl('type'),TokenIdent('Point'),TokenLeftBrace('{'),TokenIdent('x'),TokenComma(','),TokenIdent('y'),TokenRightBrace('}'),TokenIdent('p'),TokenDot('.'),TokenIdent('x'),TokenWith('with'),TokenIdent('typed')]");
+
+    let code="let mut mutable = muts;";
+    let mut s=Scanner::new(code);
+    assert_eq!(s.serialize(), "[TokenLet('let'),TokenMut('mut'),TokenIdent('mutable'),TokenEqual('='),TokenIdent('muts'),TokenSemiColon(';')]");
+
+    let code="import \"lib/utils\" as u; export fun asked imports";
+    let mut s=Scanner::new(code);
+    assert_eq!(s.serialize(), "[TokenImport('import'),TokenStringQuote('\"'),TokenString('lib/utils'),TokenStringQuote('\"'),TokenAs('as'),TokenIdent('u'),TokenSemiColon(';'),TokenExport('export'),TokenFunc('fun'),TokenIdent('asked'),TokenIdent('imports')]");
+
+    let code="a += 1 -= b *= 2 ** c /= d -> e";
+    let mut s=Scanner::new(code);
+    assert_eq!(s.serialize(), "[TokenIdent('a'),TokenPlusEq('+='),TokenInteger('1'),TokenMinusEq('-='),TokenIdent('b'),TokenStarEq('*='),TokenInteger('2'),TokenStarStar('**'),TokenIdent('c'),TokenSlashEq('/='),TokenIdent('d'),TokenLambda('->'),TokenIdent('e')]");
+
+    let code="match xs { [h, ..t] => h, matches=>_ }";
+    let mut s=Scanner::new(code);
+    assert_eq!(s.serialize(), "[TokenMatch('match'),TokenIdent('xs'),TokenLeftBrace('{'),TokenLeftBracket('['),TokenIdent('h'),TokenComma(','),TokenRange('..'),TokenIdent('t'),TokenRightBracket(']'),TokenFatArrow('=>'),TokenIdent('h'),TokenComma(','),TokenIdent('matches'),TokenFatArrow('=>'),TokenIdent('_'),TokenRightBrace('}')]");
 }
 
 #[test]
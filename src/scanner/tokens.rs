@@ -12,20 +12,33 @@ pub enum TokenType {
     TokenRightParen, // delim
     TokenLeftBrace, // delim
     TokenRightBrace, // delim
+    TokenLeftBracket, // delim
+    TokenRightBracket, // delim
+    TokenMapOpen, // #{ - closed by TokenRightBrace
     TokenStringQuote, // delim - "\""
     TokenComma,
+    TokenColon,
     TokenDot,
     TokenMinus,
     TokenPlus,
     TokenSemiColon,
     TokenSlash,
     TokenStar,
+    TokenPercent,
+    TokenAmpersand, // bitwise and
+    TokenBar, // bitwise or
+    TokenCaret, // bitwise xor
 
     // Keywords
     TokenPrint,
     TokenReturn,
     TokenIf,
     TokenElse,
+    TokenWhile,
+    TokenFor,
+    TokenIn,
+    TokenBreak,
+    TokenContinue,
     TokenTrue,
     TokenFalse,
     TokenAnd,
@@ -33,6 +46,15 @@ pub enum TokenType {
     TokenPipe,
     TokenFunc,
     TokenLet,
+    TokenMut,
+    TokenTypeDecl, // type
+    TokenWith,
+    TokenImport,
+    TokenExport,
+    TokenAs, // import name as alias
+    TokenInfixl, // infixl 5 $name;
+    TokenInfixr,
+    TokenMatch,
 
     // Literals
     TokenInteger,
@@ -55,6 +77,15 @@ pub enum TokenType {
     TokenError,
     TokenLambda,
     TokenInfix,
+    TokenRange, // ..
+    TokenStarStar, // **
+    TokenShiftLeft, // <<
+    TokenShiftRight, // >>> logical shift: >> is the pipe
+    TokenPlusEq, // +=
+    TokenMinusEq, // -=
+    TokenStarEq, // *=
+    TokenSlashEq, // /=
+    TokenFatArrow, // =>
 }
 
 
@@ -157,7 +188,7 @@ impl<'src>  Token<'src> {
 
 // store lookahead of one char i.e the Option<char> after peek
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LookaheadChars<'src> {
     chars:Peekable<Chars<'src>>,
     peek:Option<char> // current peek (chars always points one step ahead of peek)
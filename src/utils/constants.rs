@@ -19,11 +19,16 @@ pub const EMPTY: char = '\0';
 pub const DOT:char='.';
 pub const STMT_END:char = ';';
 pub const COMMA:char=',';
+pub const COLON:char=':';
 
 pub const PLUS:char='+';
 pub const SLASH:char='/';
 pub const STAR:char='*';
 pub const MINUS:char='-';
+pub const PERCENT:char='%';
+pub const AMPERSAND:char='&';
+pub const BAR:char='|';
+pub const CARET:char='^';
 
 pub const EQ:char='=';
 pub const BANG:char='!';
@@ -36,6 +41,7 @@ pub const LEFT_BRACE:char='{';
 pub const RIGHT_BRACE:char='}';
 
 pub const UNDERSCORE:char='_';
+pub const BACKSLASH:char='\\';
 pub const INFIX:char='$';
 
 // multi char tokens
@@ -43,12 +49,26 @@ pub const EQ_EQ:&str="==";
 pub const NOT_EQ:&str="!=";
 pub const LT_EQ:&str="<=";
 pub const GT_EQ:&str=">=";
+pub const STAR_STAR:&str="**";
+pub const SHIFT_LEFT:&str="<<";
+pub const SHIFT_RIGHT:&str=">>>"; // >> is the pipe
+pub const PLUS_EQ:&str="+=";
+pub const MINUS_EQ:&str="-=";
+pub const STAR_EQ:&str="*=";
+pub const SLASH_EQ:&str="/=";
 
 // Keywords
 pub const TOKEN_PRINT: &str = "print";
 pub const TOKEN_RETURN: &str = "return";
 pub const TOKEN_IF: &str = "if";
 pub const TOKEN_ELSE: &str = "else";
+pub const TOKEN_WHILE: &str = "while";
+pub const TOKEN_FOR: &str = "for";
+pub const TOKEN_IN: &str = "in";
+pub const TOKEN_BREAK: &str = "break";
+pub const TOKEN_CONTINUE: &str = "continue";
+pub const TOKEN_RANGE: &str = "..";
+pub const OPEN_MAP: &str = "#{";
 pub const TOKEN_TRUE: &str = "true";
 pub const TOKEN_FALSE: &str = "false";
 pub const TOKEN_AND: &str = "and";
@@ -57,6 +77,15 @@ pub const TOKEN_PIPE: &str = ">>";
 pub const TOKEN_LAMBDA:&str="->";
 pub const TOKEN_FUNC: &str = "fun";
 pub const TOKEN_LET: &str = "let";
+pub const TOKEN_MUT: &str = "mut";
+pub const TOKEN_TYPE: &str = "type";
+pub const TOKEN_WITH: &str = "with";
+pub const TOKEN_IMPORT: &str = "import";
+pub const TOKEN_EXPORT: &str = "export";
+pub const TOKEN_AS: &str = "as";
+pub const TOKEN_INFIXL: &str = "infixl";
+pub const TOKEN_INFIXR: &str = "infixr";
+pub const TOKEN_MATCH: &str = "match";
 
 // we know for sure the char is static -> this is ok
 fn cstr(char:char)->&'static str {
@@ -71,15 +100,22 @@ fn setup_keywords()->Trie<&'static str,TokenType>{
     trie.add_key(cstr(CLOSE_EXPR), TokenRightParen);
     trie.add_key(cstr(LEFT_BRACE), TokenLeftBrace);
     trie.add_key(cstr(RIGHT_BRACE), TokenRightBrace);
+    trie.add_key(cstr(OPEN_LIST), TokenLeftBracket);
+    trie.add_key(cstr(CLOSE_LIST), TokenRightBracket);
     trie.add_key(cstr(STRING_QUOTE), TokenStringQuote);
 
     trie.add_key(cstr(STMT_END), TokenSemiColon);
     trie.add_key(cstr(COMMA), TokenComma);
+    trie.add_key(cstr(COLON), TokenColon);
     trie.add_key(cstr(DOT), TokenDot);
     trie.add_key(cstr(PLUS), TokenPlus);
     trie.add_key(cstr(MINUS), TokenMinus);
     trie.add_key(cstr(SLASH), TokenSlash);
     trie.add_key(cstr(STAR), TokenStar);
+    trie.add_key(cstr(PERCENT), TokenPercent);
+    trie.add_key(cstr(AMPERSAND), TokenAmpersand);
+    trie.add_key(cstr(BAR), TokenBar);
+    trie.add_key(cstr(CARET), TokenCaret);
 
     // comp
     trie.add_key(cstr(EQ), TokenEqual);
@@ -95,12 +131,26 @@ fn setup_keywords()->Trie<&'static str,TokenType>{
     trie.add_key(NOT_EQ, TokenNotEq);
     trie.add_key(LT_EQ, TokenLessEq);
     trie.add_key(GT_EQ, TokenGtEq);
+    trie.add_key(STAR_STAR, TokenStarStar);
+    trie.add_key(SHIFT_LEFT, TokenShiftLeft);
+    trie.add_key(SHIFT_RIGHT, TokenShiftRight);
+    trie.add_key(PLUS_EQ, TokenPlusEq);
+    trie.add_key(MINUS_EQ, TokenMinusEq);
+    trie.add_key(STAR_EQ, TokenStarEq);
+    trie.add_key(SLASH_EQ, TokenSlashEq);
 
     // keywords
     trie.add_key(TOKEN_PRINT, TokenPrint);
     trie.add_key(TOKEN_RETURN, TokenReturn);
     trie.add_key(TOKEN_IF, TokenIf);
     trie.add_key(TOKEN_ELSE, TokenElse);
+    trie.add_key(TOKEN_WHILE, TokenWhile);
+    trie.add_key(TOKEN_FOR, TokenFor);
+    trie.add_key(TOKEN_IN, TokenIn);
+    trie.add_key(TOKEN_BREAK, TokenBreak);
+    trie.add_key(TOKEN_CONTINUE, TokenContinue);
+    trie.add_key(TOKEN_RANGE, TokenRange);
+    trie.add_key(OPEN_MAP, TokenMapOpen);
     trie.add_key(TOKEN_TRUE, TokenTrue);
     trie.add_key(TOKEN_FALSE, TokenFalse);
     trie.add_key(TOKEN_AND, TokenAnd);
@@ -109,6 +159,16 @@ fn setup_keywords()->Trie<&'static str,TokenType>{
     trie.add_key(TOKEN_LAMBDA, TokenLambda);
     trie.add_key(TOKEN_FUNC, TokenFunc);
     trie.add_key(TOKEN_LET, TokenLet);
+    trie.add_key(TOKEN_INFIXL, TokenInfixl);
+    trie.add_key(TOKEN_INFIXR, TokenInfixr);
+    trie.add_key(TOKEN_MATCH, TokenMatch);
+    trie.add_key(TOKEN_MUT, TokenMut);
+    trie.add_key(TOKEN_TYPE, TokenTypeDecl);
+    trie.add_key(TOKEN_WITH, TokenWith);
+    trie.add_key(TOKEN_IMPORT, TokenImport);
+    trie.add_key(TOKEN_EXPORT, TokenExport);
+    trie.add_key(TOKEN_AS, TokenAs);
+    trie.add_key(FAT_ARROW, TokenFatArrow);
 
     trie
 }
@@ -23,15 +23,51 @@ fn read_file(filename: &str) -> Result<String> {
     }
 }
 
-use crate::data::ops::Value;
-pub fn run_file(filename:&str, vm:&mut VM)->Result<Value> {
+// name with .txt added if it has no extension
+fn with_extension(filename:&str)->String {
     let mut file=filename.to_string();
 
     if !file.ends_with(".txt") {
         file.push_str(".txt");
     }
+    file
+}
+
+/// Full path of the file for the module imported as name: relative paths start from dir
+pub fn module_path(dir:&Path, name:&str)->Result<PathBuf> {
+    let path=dir.join(get_full_path(&with_extension(name)));
+
+    match path.canonicalize() {
+        Ok(path) => Ok(path),
+        Err(_) => {
+            let msg=format!("Module '{}' not found: no file at {}", name, path.display());
+            err_other!(msg)
+        }
+    }
+}
+
+/// Source of the module at path
+pub fn read_module(path:&Path)->Result<String> {
+    match read_to_string(path) {
+        Ok(source) => Ok(source),
+        Err(err) => {
+            let msg=format!("Could not read module at {}: {}", path.display(), err);
+            err_other!(msg)
+        }
+    }
+}
+
+use crate::data::ops::Value;
+pub fn run_file(filename:&str, vm:&mut VM)->Result<Value> {
+    let file=with_extension(filename);
 
     let source=read_file(&file)?;
+
+    // imports in the file are relative to its directory
+    if let Some(dir) = get_full_path(&file).parent() {
+        vm.set_dir(dir);
+    }
+
     // dont reset vm
    vm.interpret_with_reset(&source, false)
 }
\ No newline at end of file
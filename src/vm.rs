@@ -1,16 +1,91 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 use std::hash::Hash;
+use std::path::{Path, PathBuf};
 use std::process::id;
 
 use log::debug;
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
 
-use crate::data::{ops::*, stack::*};
+use crate::data::{ops::*, stack::*, obj::*};
 use crate::parser::parser::*;
 use crate::utils::err::*;
 use crate::data::ops::Inst::*;
 use crate::utils::misc::{calc_hash, StringIntern};
+use crate::natives::{find_native, NATIVES};
+use crate::utils::file::{module_path, read_module};
 
 const VAL_STACK_MAX:usize=2000;
+const FRAMES_MAX:usize=256;
+
+/// Closure being executed: slots is the index in the value stack of the callee, which is followed by the args
+#[derive(Debug)]
+pub struct CallFrame {
+    closure:Rc<Closure>,
+    ip:usize, // only kept up to date for callers: the running frame uses VM.ip
+    slots:usize
+}
+
+impl CallFrame {
+    pub fn new(closure:Rc<Closure>, slots:usize)->CallFrame {
+        CallFrame { closure, ip: 0, slots }
+    }
+}
+
+/// Global namespace of a file: the code being run is module 0 and each imported file gets its own
+#[derive(Debug)]
+struct Module {
+    name:String,
+    dir:PathBuf, // imports in the module are relative to this
+    globals:HashMap<u64,Value>, // store u64 hash -> value instead
+    mutable_globals:HashSet<u64>, // hash of globals declared with let mut
+    exports:HashSet<u64>, // hash of globals importers can access
+    loaded:bool // false while its code is running: importing it again then is a cycle
+}
+
+impl Module {
+    fn new(name:&str, dir:PathBuf)->Module {
+        Module { name: name.to_string(), dir, globals: HashMap::new(), mutable_globals: HashSet::new(), exports: HashSet::new(), loaded: false }
+    }
+}
+
+/// base ** exp wrapping around on overflow, for any non-negative exp
+fn wrapping_pow(mut base:IntType, mut exp:IntType)->IntType {
+    let mut res:IntType=1;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            res=res.wrapping_mul(base);
+        }
+        base=base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    res
+}
+
+/// Position of the field called name in records of type ty: errors if it has no such field
+fn field_idx(ty:&RecordType, name:&str)->Result<usize> {
+    match ty.field_idx(name) {
+        Some(idx) => Ok(idx),
+        None => {
+            let msg=format!("Record {} has no field '{}'", ty.name, name);
+            err_other!(msg)
+        }
+    }
+}
+
+/// idx as a position in a sequence of len items of the given kind: errors if out of bounds
+fn checked_index(idx:Value, len:usize, kind:&str)->Result<usize> {
+    let idx=idx.expect_int()?;
+
+    if idx < 0 || idx as usize >= len {
+        let msg=format!("Index {} out of bounds for {} of length {}", idx, kind, len);
+        return err_other!(msg);
+    }
+
+    Ok(idx as usize)
+}
 
 // may not need to store chunk
 
@@ -36,10 +111,14 @@ const VAL_STACK_MAX:usize=2000;
 pub struct VM {
     ip:usize, // index of next op to execute,
     value_stack:FixedStack<Value>, // this should have same layout as Compiler.locals,
-    globals:HashMap<u64,Value>, // store u64 hash -> value instead
-    // call_stack: VecStack<CallFrame<'function>> 
-        // call frame refers to function potentially on value stack
-    strings:StringIntern 
+    modules:Vec<Module>, // globals of the code being run followed by each imported module
+    module_paths:HashMap<PathBuf,usize>, // full path of an imported file -> idx in modules so it only runs once
+    importing:Vec<usize>, // modules whose code is running, innermost last
+    call_stack:VecStack<CallFrame>,
+    pub(crate) heap:Heap,
+    open_upvalues:Vec<usize>, // heap idx of upvalues still pointing into the value stack
+    pub(crate) strings:StringIntern,
+    wrapping:bool // integer overflow wraps around instead of making a big int
 }
 
 // VM: runtime (compilation ends with the chunk)
@@ -55,33 +134,144 @@ impl VM {
         VM {
             ip:0,
             value_stack:FixedStack::new(),
-            globals:HashMap::new(),
-            strings:StringIntern::new()
+            modules:vec![Module::new("main", PathBuf::new())],
+            module_paths:HashMap::new(),
+            importing:vec![],
+            call_stack:VecStack::new(FRAMES_MAX),
+            heap:Heap::new(),
+            open_upvalues:vec![],
+            strings:StringIntern::new(),
+            wrapping:false
         }
     }
 
+    /// Ints that overflow become big ints by default: in wrapping mode they wrap around instead.
+    /// Division by zero is an error either way
+    pub fn set_wrapping(&mut self, wrapping:bool) {
+        self.wrapping=wrapping;
+    }
+
+    /// Directory that imports in the code being run are relative to: the current directory by default
+    pub fn set_dir(&mut self, dir:&Path) {
+        self.modules[0].dir=dir.to_path_buf();
+    }
+
     fn reset(&mut self) {
         // self.ip=0;
         // self.value_stack.clear();
-        self.globals.clear();
+        let dir=std::mem::take(&mut self.modules[0].dir);
+        self.modules=vec![Module::new("main", dir)];
+        self.module_paths.clear();
         self.strings.clear();
+        self.heap.clear();
+    }
+
+    /// idx in modules of the module the running code belongs to
+    fn module(&self)->usize {
+        self.call_stack.peek().map(|frame| frame.closure.module).unwrap_or(0)
+    }
+
+    /// Module the running code belongs to: its globals are the ones variables refer to
+    fn current_module(&mut self)->&mut Module {
+        let idx=self.module();
+        &mut self.modules[idx]
     }
 
     /// Add global variable given identifier
     fn add_global(&mut self, identifier:String, value:Value) {
         let hash=self.strings.add_string(identifier);
-        self.globals.insert(hash, value);
+        self.current_module().globals.insert(hash, value);
     }
 
     fn get_global(&self, identifier:&String)->Option<&Value> {
         let hash=calc_hash(identifier);
-        self.globals.get(&hash)
+        self.modules[self.module()].globals.get(&hash)
+    }
+
+    /// Only a global declared with let mut can be assigned to
+    fn check_assign_global(&mut self, identifier:&String)->Result<()> {
+        if self.get_global(identifier).is_none() {
+            let msg=format!("Cannot assign to undeclared variable '{}'", identifier);
+            return err_other!(msg);
+        }
+
+        if !self.current_module().mutable_globals.contains(&calc_hash(identifier)) {
+            let msg=format!("Cannot assign to immutable variable '{}': declare it with let mut", identifier);
+            return err_other!(msg);
+        }
+
+        Ok(())
     }
 
     /// Get global value given string name
     pub fn get_global_value<K>(&self, name:K)->Option<&Value> where K:ToString{
         let hash=calc_hash(&name.to_string());
-        self.globals.get(&hash)
+        self.modules[0].globals.get(&hash)
+    }
+
+    /// Global called name that the module exports
+    fn get_export(&self, idx:usize, name:&str)->Result<Value> {
+        let module=&self.modules[idx];
+        let hash=calc_hash(name);
+
+        match module.globals.get(&hash) {
+            Some(value) if module.exports.contains(&hash) => Ok(*value),
+            _ => {
+                let msg=format!("Module {} does not export '{}'", module.name, name);
+                err_other!(msg)
+            }
+        }
+    }
+
+    /// Push the module for the file at path, which is relative to the importing module's directory.
+    /// Returns true if a new frame was pushed to run the module's code, which only happens the first time
+    fn import_module(&mut self, path:&str)->Result<bool> {
+        let importer=self.module();
+        let full_path=module_path(&self.modules[importer].dir, path)?;
+
+        if let Some(&idx) = self.module_paths.get(&full_path) {
+            if !self.modules[idx].loaded {
+                // chain of imports from the module back to itself
+                let start=self.importing.iter().position(|m| *m==idx).unwrap_or(0);
+                let names:Vec<&str>=self.importing[start..].iter().chain([&idx])
+                    .map(|m| self.modules[*m].name.as_str())
+                    .collect();
+
+                let msg=format!("Import cycle: {}", names.join(" -> "));
+                return err_other!(msg);
+            }
+
+            self.value_stack.push(Value::ObjModule(idx))?;
+            return Ok(false);
+        }
+
+        let name=full_path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        let source=read_module(&full_path)?;
+
+        let mut chunk=Chunk::new();
+        let mut parser=Parser::new(&source);
+        if let Err(err) = parser.compile_module(&mut chunk) {
+            let msg=format!("Error in module {}: {}", name, err);
+            return err_other!(msg);
+        }
+
+        let idx=self.modules.len();
+        let dir=full_path.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.modules.push(Module::new(&name, dir));
+        self.module_paths.insert(full_path, idx);
+        self.importing.push(idx);
+
+        // save where the importer resumes
+        if let Some(frame) = self.call_stack.peek_mut() {
+            frame.ip=self.ip;
+        }
+
+        // the module's code runs like the top level code of a script, with locals after the importer's values
+        let script=Rc::new(Function::script(chunk));
+        let closure=Rc::new(Closure::new(script, vec![], idx));
+        self.push_frame(CallFrame::new(closure, self.value_stack.len()))?;
+        self.ip=0;
+        Ok(true)
     }
 
     /// returns string interned in hash
@@ -105,27 +295,259 @@ impl VM {
     // Always clear val stack and ip before run. reset: clear variables and strings
     pub fn run(&mut self, chunk:&mut Chunk, reset:bool)->Result<Value> {
         self.value_stack.clear();
+        self.call_stack.clear();
+        self.open_upvalues.clear();
         self.ip = 0;
 
         if reset {
             self.reset();
         }
 
+        // modules that failed to load run again when imported next
+        self.importing.clear();
+        let modules=&self.modules;
+        self.module_paths.retain(|_, idx| modules[*idx].loaded);
+
+        // top level code runs as the first frame
+        let script=Rc::new(Function::script(std::mem::take(chunk)));
+        let closure=Rc::new(Closure::new(script.clone(), vec![], 0));
+        self.push_frame(CallFrame::new(closure, 0))?;
+
+        let res=match self.execute() {
+            Ok(val) => Ok(val),
+            Err(msg) => {
+                // line of the failing op in the innermost frame
+                let line=self.current_function().chunk.get_line_of_op(self.ip).unwrap();
+
+                let msg=format!("[line {}] {}", line, msg);
+                errn!(msg)
+            }
+        };
+
+        // hand the chunk back to the caller
+        self.call_stack.clear();
+        self.open_upvalues.clear();
+        if let Ok(script) = Rc::try_unwrap(script) {
+            *chunk=script.chunk;
+        }
+
+        res
+    }
+
+    /// Free heap objects that the program can no longer reach
+    fn collect_garbage(&mut self) {
+        let mut roots:Vec<Value>=(0..self.value_stack.len()).filter_map(|idx| self.value_stack.get(idx)).collect();
+        roots.extend(self.modules.iter().flat_map(|module| module.globals.values().copied()));
+
+        let mut objects=self.open_upvalues.clone();
+        for frame in self.call_stack.stack.iter() {
+            objects.extend(frame.closure.upvalues.iter());
+        }
+
+        let before=self.heap.live();
+        self.heap.collect(roots, objects);
+        debug!("GC: {} objects live, {} freed", self.heap.live(), before-self.heap.live());
+    }
+
+    /// Number of heap objects that have not been freed
+    pub fn live_objects(&self)->usize {
+        self.heap.live()
+    }
+
+    /// Start running frame: errors once calls are nested FRAMES_MAX deep, which is usually runaway recursion
+    fn push_frame(&mut self, frame:CallFrame)->Result<()> {
+        if self.call_stack.len() >= FRAMES_MAX {
+            let msg=format!("Maximum call depth {} exceeded", FRAMES_MAX);
+            return err_other!(msg);
+        }
+
+        self.call_stack.push(frame)
+    }
+
+    /// Closure of the frame being executed
+    fn current_closure(&self)->Rc<Closure> {
+        self.call_stack.peek().expect("No call frame").closure.clone()
+    }
+
+    /// Function of the frame being executed
+    fn current_function(&self)->Rc<Function> {
+        self.current_closure().function.clone()
+    }
+
+    /// Start of the current frame in the value stack: locals are relative to this
+    fn frame_slots(&self)->usize {
+        self.call_stack.peek().map(|frame| frame.slots).unwrap_or(0)
+    }
+
+    /// Call the value below the args on the stack.
+    /// Returns true if a new frame was pushed, false if the call already finished (natives)
+    fn call_value(&mut self, argc:usize)->Result<bool> {
+        let callee_slot=self.value_stack.len()-1-argc;
+        let callee=self.value_stack.get(callee_slot).expect("Missing callee below args");
+
+        match callee {
+            Value::ObjClosure(idx) => {
+                let closure=self.heap.expect_closure(idx)?;
+                let arity=closure.function.arity;
+                if arity != argc {
+                    let msg=format!("Expected {} arguments but got {}", arity, argc);
+                    return err_other!(msg);
+                }
+
+                // save where the caller resumes
+                if let Some(frame) = self.call_stack.peek_mut() {
+                    frame.ip=self.ip;
+                }
+
+                self.push_frame(CallFrame::new(closure, callee_slot))?;
+                self.ip=0;
+                Ok(true)
+            },
+            Value::Native(idx) => {
+                let native=&NATIVES[idx];
+                if native.arity != argc {
+                    let msg=format!("Expected {} arguments but got {}", native.arity, argc);
+                    return err_other!(msg);
+                }
+
+                let args:Vec<Value>=(callee_slot+1..self.value_stack.len())
+                    .map(|slot| self.value_stack.get(slot).unwrap())
+                    .collect();
+                let res=(native.function)(self, &args)?;
+
+                self.value_stack.truncate(callee_slot);
+                self.value_stack.push(res)?;
+                Ok(false)
+            },
+            // types are called like functions to make a record from each field in order e.g Circle(2)
+            Value::ObjType(idx) => {
+                let ty=self.heap.expect_type(idx)?;
+                if ty.fields.len() != argc {
+                    let msg=format!("Expected {} arguments but got {}", ty.fields.len(), argc);
+                    return err_other!(msg);
+                }
+
+                let fields=self.pop_many(argc);
+                self.value_stack.truncate(callee_slot);
+                let obj=self.heap.alloc(Obj::Record(Record { ty, fields }));
+                self.value_stack.push(Value::ObjRecord(obj))?;
+                Ok(false)
+            },
+            _ => err_other!("Can only call functions but got {}", callee.type_name())
+        }
+    }
+
+    /// Upvalue pointing at slot in the value stack: reuse an open one so closures share the variable
+    fn capture_upvalue(&mut self, slot:usize)->usize {
+        for idx in self.open_upvalues.iter() {
+            if let Ok(Upvalue::Open(s)) = self.heap.expect_upvalue(*idx) {
+                if s==slot {
+                    return *idx;
+                }
+            }
+        }
+
+        let idx=self.heap.alloc(Obj::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.push(idx);
+        idx
+    }
+
+    /// Move values of open upvalues at slot or above off the stack: their variables are about to be popped
+    fn close_upvalues(&mut self, slot:usize) {
+        let mut still_open=vec![];
+
+        for idx in std::mem::take(&mut self.open_upvalues) {
+            match self.heap.expect_upvalue(idx) {
+                Ok(Upvalue::Open(s)) if s >= slot => {
+                    let value=self.value_stack.get(s).unwrap_or(Value::Unit);
+                    self.heap.set_upvalue(idx, Upvalue::Closed(value));
+                },
+                _ => still_open.push(idx)
+            }
+        }
+
+        self.open_upvalues=still_open;
+    }
+
+    fn get_upvalue(&self, idx:usize)->Result<Value> {
+        let closure=self.current_closure();
+        let heap_idx=closure.upvalues.get(idx).ok_or(errn_i!("Invalid upvalue index"))?;
+
+        match self.heap.expect_upvalue(*heap_idx)? {
+            Upvalue::Open(slot) => self.value_stack.get(slot).ok_or(errn_i!("Captured variable is not on the stack")),
+            Upvalue::Closed(value) => Ok(value)
+        }
+    }
+
+    fn set_upvalue(&mut self, idx:usize, value:Value)->Result<()> {
+        let closure=self.current_closure();
+        let heap_idx=*closure.upvalues.get(idx).ok_or(errn_i!("Invalid upvalue index"))?;
+
+        match self.heap.expect_upvalue(heap_idx)? {
+            Upvalue::Open(slot) => self.value_stack.set(slot, value),
+            Upvalue::Closed(_) => self.heap.set_upvalue(heap_idx, Upvalue::Closed(value))
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self)->Result<Value> {
+        // ints stay ints, promote both sides to float if either is a float
+        // ints use the checked op: on overflow the big int op, or the wrapping one in wrapping mode
+        macro_rules! arith {
+            ($left:expr, $right:expr, $op:tt, $checked:ident, $wrapping:ident) => {
+                if $left.is_float() || $right.is_float() {
+                    Value::Float(self.expect_float($left)? $op self.expect_float($right)?)
+                } else {
+                    self.int_arith($left, $right, stringify!($op), IntType::$checked, IntType::$wrapping, |l, r| l $op r)?
+                }
+            };
+        }
+
         macro_rules! bin_op {
+            ($op:tt, $checked:ident, $wrapping:ident) => {
+                {
+                    let right=self.value_stack.pop()?;
+                    let left=self.value_stack.pop()?;
+                    let res=arith!(left, right, $op, $checked, $wrapping);
+                    self.value_stack.push(res)?;
+                }
+            };
+        }
+
+        // bitwise op: both operands must be integers
+        macro_rules! int_op {
             ($op:tt) => {
                 {
-                    let stack=&mut self.value_stack;
-                    let right=stack.pop()?.expect_int()?;
-                    let left=stack.pop()?.expect_int()?;
-                    stack.push(Value::num(left $op right))?;
+                    let right=self.value_stack.pop()?.expect_int()?;
+                    let left=self.value_stack.pop()?.expect_int()?;
+                    self.value_stack.push(Value::num(left $op right))?;
+                }
+            };
+        }
+
+        // push result of comparing left and right with the given ordering op e.g <, >=
+        macro_rules! cmp_op {
+            ($op:tt) => {
+                {
+                    let right=self.value_stack.pop()?;
+                    let left=self.value_stack.pop()?;
+                    let ord=self.compare_values(left, right)?;
+                    self.value_stack.push(Value::Bool(ord $op Ordering::Equal))?;
                 }
             };
         }
-        
 
-        log::debug!("Chunk at start:{}", chunk);
+        log::debug!("Chunk at start:{}", self.current_function().chunk);
 
         loop {
+            // between ops every value in use is reachable from the stack, globals, upvalues or frames
+            if self.heap.should_collect() {
+                self.collect_garbage();
+            }
+
+            let function=self.current_function();
+            let chunk=&function.chunk;
+
             // let curr=self.get_curr_inst(&chunk);
             let curr=chunk.get_op(self.ip);
             debug!("CURR_OP:{:?}", curr);
@@ -140,11 +562,322 @@ impl VM {
                 OpReturn => {
                     let res=self.value_stack.pop()?;
                     log::debug!("Return:{}", res);
-                    break Ok(res);
+
+                    let frame=self.call_stack.pop()?;
+                    if self.call_stack.is_empty() {
+                        break Ok(res);
+                    }
+
+                    // discard callee and its args/locals then resume the caller after OpCall
+                    self.close_upvalues(frame.slots);
+                    self.value_stack.truncate(frame.slots);
+                    self.value_stack.push(res)?;
+                    self.ip=self.call_stack.peek().unwrap().ip;
+                },
+                OpCall(argc) => {
+                    if self.call_value(*argc)? {
+                        continue; // new frame starts at ip 0
+                    }
+                },
+                OpRange => {
+                    let end=self.value_stack.pop()?.expect_int()?;
+                    let start=self.value_stack.pop()?.expect_int()?;
+                    let obj=self.heap.alloc(Obj::Range(start, end));
+                    self.value_stack.push(Value::ObjRange(obj))?;
+                },
+                OpList(count) => {
+                    let start=self.value_stack.len()-count;
+                    let items=(start..self.value_stack.len()).map(|idx| self.value_stack.get(idx).unwrap()).collect();
+                    self.value_stack.truncate(start);
+
+                    let obj=self.heap.alloc(Obj::List(items));
+                    self.value_stack.push(Value::ObjList(obj))?;
+                },
+                OpMap(count) => {
+                    let start=self.value_stack.len()-2*count;
+                    let mut map=Map::new();
+
+                    for idx in (start..self.value_stack.len()).step_by(2) {
+                        let key_value=self.value_stack.get(idx).unwrap();
+                        let value=self.value_stack.get(idx+1).unwrap();
+                        map.insert(self.heap.key(key_value)?, key_value, value);
+                    }
+                    self.value_stack.truncate(start);
+
+                    let obj=self.heap.alloc(Obj::Map(map));
+                    self.value_stack.push(Value::ObjMap(obj))?;
+                },
+                OpTuple(count) => {
+                    let start=self.value_stack.len()-count;
+                    let items=(start..self.value_stack.len()).map(|idx| self.value_stack.get(idx).unwrap()).collect();
+                    self.value_stack.truncate(start);
+
+                    let obj=self.heap.alloc(Obj::Tuple(items));
+                    self.value_stack.push(Value::ObjTuple(obj))?;
+                },
+                OpUnpack(count) => {
+                    let value=self.value_stack.pop()?;
+                    let items=match value {
+                        Value::ObjTuple(obj) => self.heap.expect_tuple(obj)?.clone(),
+                        _ => {
+                            let msg=format!("Cannot destructure {} as a tuple", value.type_name());
+                            return err_other!(msg);
+                        }
+                    };
+
+                    if items.len() != *count {
+                        let msg=format!("Expected tuple of {} items but got {}", count, items.len());
+                        return err_other!(msg);
+                    }
+
+                    for item in items {
+                        self.value_stack.push(item)?;
+                    }
+                },
+                OpIndex => {
+                    let idx=self.value_stack.pop()?;
+                    let target=self.value_stack.pop()?;
+
+                    let item=match target {
+                        Value::ObjMap(obj) => match self.heap.expect_map(obj)?.get(&self.heap.key(idx)?) {
+                            Some(value) => value,
+                            None => {
+                                let msg=format!("Key {} not found in map", self.print_value(idx));
+                                return err_other!(msg);
+                            }
+                        },
+                        Value::ObjTuple(obj) => {
+                            let items=self.heap.expect_tuple(obj)?;
+                            items[checked_index(idx, items.len(), "tuple")?]
+                        },
+                        _ => {
+                            let (items, idx)=self.list_index(target, idx)?;
+                            self.heap.expect_list(items)?[idx]
+                        }
+                    };
+                    self.value_stack.push(item)?;
+                },
+                OpSetIndex => {
+                    let value=self.value_stack.pop()?;
+                    let idx=self.value_stack.pop()?;
+                    let target=self.value_stack.pop()?;
+
+                    if let Value::ObjMap(obj) = target {
+                        let key=self.heap.key(idx)?;
+                        self.heap.expect_map_mut(obj)?.insert(key, idx, value);
+                    } else if let Value::ObjTuple(_) = target {
+                        return err_other!("Cannot assign to an item of a tuple");
+                    } else {
+                        let (items, idx)=self.list_index(target, idx)?;
+                        self.heap.expect_list_mut(items)?[idx]=value;
+                    }
+                },
+                OpTestTuple(count) => {
+                    let value=self.value_stack.pop()?;
+                    let is_match=match value {
+                        Value::ObjTuple(obj) => self.heap.expect_tuple(obj)?.len()==*count,
+                        _ => false
+                    };
+                    self.value_stack.push(Value::Bool(is_match))?;
+                },
+                OpTestList(count, has_rest) => {
+                    let value=self.value_stack.pop()?;
+                    let is_match=match value {
+                        Value::ObjList(obj) => {
+                            let len=self.heap.expect_list(obj)?.len();
+                            len==*count || (*has_rest && len > *count)
+                        },
+                        _ => false
+                    };
+                    self.value_stack.push(Value::Bool(is_match))?;
+                },
+                OpListFrom(start) => {
+                    let obj=match self.value_stack.pop()? {
+                        Value::ObjList(obj) => obj,
+                        value => {
+                            let msg=format!("Expected list but got {}", value.type_name());
+                            return err_other!(msg);
+                        }
+                    };
+                    let items=self.heap.expect_list(obj)?[*start..].to_vec();
+                    let list=self.alloc_list(items);
+                    self.value_stack.push(list)?;
+                },
+                OpToString => {
+                    let value=self.value_stack.pop()?;
+                    let string=match value {
+                        Value::ObjString(_) => value,
+                        _ => {
+                            let string=self.print_value(value);
+                            Value::ObjString(self.strings.add_string(string))
+                        }
+                    };
+                    self.value_stack.push(string)?;
+                },
+                OpType(idx) => {
+                    let ty=chunk.get_type(*idx).ok_or(errn_i!("Invalid index for type:{}", idx))?;
+                    let obj=self.heap.alloc(Obj::Type(ty));
+                    self.value_stack.push(Value::ObjType(obj))?;
+                },
+                OpRecord(names) => {
+                    let values=self.pop_many(names.len());
+                    let ty=match self.value_stack.pop()? {
+                        Value::ObjType(idx) => self.heap.expect_type(idx)?,
+                        other => {
+                            let msg=format!("Can only make a record of a type but got {}", other.type_name());
+                            return err_other!(msg);
+                        }
+                    };
+
+                    let mut fields=vec![None; ty.fields.len()];
+                    for (name, value) in names.iter().zip(values) {
+                        fields[field_idx(&ty, name)?]=Some(value);
+                    }
+
+                    let fields=fields.into_iter().zip(ty.fields.iter()).map(|(value, name)| match value {
+                        Some(value) => Ok(value),
+                        None => {
+                            let msg=format!("Missing field '{}' for record {}", name, ty.name);
+                            err_other!(msg)
+                        }
+                    }).collect::<Result<Vec<Value>>>()?;
+
+                    let obj=self.heap.alloc(Obj::Record(Record { ty, fields }));
+                    self.value_stack.push(Value::ObjRecord(obj))?;
+                },
+                OpGetField(name) => {
+                    let field=match self.value_stack.pop()? {
+                        Value::ObjModule(idx) => self.get_export(idx, name)?,
+                        value => {
+                            let record=self.expect_record(value)?;
+                            record.fields[field_idx(&record.ty, name)?]
+                        }
+                    };
+                    self.value_stack.push(field)?;
+                },
+                OpImport(path) => {
+                    if self.import_module(path)? {
+                        continue; // module's code starts at ip 0
+                    }
+                },
+                OpExport(name) => {
+                    let hash=calc_hash(name);
+                    self.current_module().exports.insert(hash);
+                },
+                OpEndModule => {
+                    let frame=self.call_stack.pop()?;
+                    let idx=frame.closure.module;
+                    self.modules[idx].loaded=true;
+                    self.importing.pop();
+
+                    // discard the module's values then resume the importer after OpImport
+                    self.close_upvalues(frame.slots);
+                    self.value_stack.truncate(frame.slots);
+                    self.value_stack.push(Value::ObjModule(idx))?;
+                    self.ip=self.call_stack.peek().unwrap().ip;
+                },
+                OpWith(names) => {
+                    let values=self.pop_many(names.len());
+                    let value=self.value_stack.pop()?;
+                    let mut record=self.expect_record(value)?.clone();
+
+                    for (name, value) in names.iter().zip(values) {
+                        let idx=field_idx(&record.ty, name)?;
+                        record.fields[idx]=value;
+                    }
+
+                    let obj=self.heap.alloc(Obj::Record(record));
+                    self.value_stack.push(Value::ObjRecord(obj))?;
+                },
+                OpGetFieldAt(idx) => {
+                    let value=self.value_stack.pop()?;
+                    let field=self.expect_record(value)?.fields[*idx];
+                    self.value_stack.push(field)?;
+                },
+                OpTestVariant(count) => {
+                    let ty=match self.value_stack.pop()? {
+                        Value::ObjType(idx) => self.heap.expect_type(idx)?,
+                        Value::ObjRecord(idx) => self.heap.expect_record(idx)?.ty.clone(),
+                        other => {
+                            let msg=format!("Can only match a constructor pattern against a type but got {}", other.type_name());
+                            return err_other!(msg);
+                        }
+                    };
+
+                    let is_match=match self.value_stack.pop()? {
+                        Value::ObjRecord(idx) => Rc::ptr_eq(&self.heap.expect_record(idx)?.ty, &ty),
+                        _ => false
+                    };
+
+                    if is_match && ty.fields.len() != *count {
+                        let msg=format!("Expected {} fields in pattern for {} but got {}", ty.fields.len(), ty.name, count);
+                        return err_other!(msg);
+                    }
+                    self.value_stack.push(Value::Bool(is_match))?;
+                },
+                OpNoMatch => {
+                    let value=self.value_stack.pop()?;
+                    let msg=format!("No match arm matched {}", self.print_value(value));
+                    return err_other!(msg);
+                },
+                &OpForIter(slot, exit) => {
+                    let slot=self.frame_slots() + slot;
+                    let iterable=self.value_stack.get(slot).expect("Missing iterable of for loop");
+                    let idx=self.value_stack.get(slot+1).expect("Missing index of for loop").expect_int()?;
+
+                    match self.next_item(iterable, idx)? {
+                        Some(item) => {
+                            self.value_stack.set(slot+1, Value::Number(idx+1));
+                            self.value_stack.push(item)?;
+                        },
+                        None => {
+                            self.ip=exit;
+                        }
+                    }
+                },
+                OpPipe(argc) => {
+                    // value, callee, args.. => callee, value, args..
+                    let value_slot=self.value_stack.len()-1-argc;
+                    let value=self.value_stack.get(value_slot).expect("Missing piped value");
+                    let callee=self.value_stack.get(value_slot+1).expect("Missing callee");
+                    self.value_stack.set(value_slot, callee);
+                    self.value_stack.set(value_slot+1, value);
+
+                    if self.call_value(*argc)? {
+                        continue;
+                    }
+                },
+                OpClosure(idx) => {
+                    let function=chunk.get_function(*idx).expect("Invalid function index from chunk");
+                    let slots=self.frame_slots();
+                    let enclosing=self.current_closure();
+
+                    let upvalues=function.upvalues.iter().map(|upvalue| {
+                        if upvalue.is_local {
+                            self.capture_upvalue(slots + upvalue.index)
+                        } else {
+                            enclosing.upvalues[upvalue.index]
+                        }
+                    }).collect();
+
+                    let obj=self.heap.alloc(Obj::Closure(Rc::new(Closure::new(function, upvalues, enclosing.module))));
+                    self.value_stack.push(Value::ObjClosure(obj))?;
+                },
+                OpGetUpvalue(idx) => {
+                    let value=self.get_upvalue(*idx)?;
+                    self.value_stack.push(value)?;
+                },
+                OpSetUpvalue(idx) => {
+                    let value=self.value_stack.pop()?;
+                    self.set_upvalue(*idx, value)?;
                 },
                 OpPop => {
                     self.value_stack.pop()?;
                 },
+                OpDup => {
+                    let top=self.value_stack.peek().ok_or(errn_i!("Nothing to duplicate on the stack"))?;
+                    self.value_stack.push(top)?;
+                },
                 // n = num to pop
                 // if nothing to pop => no statements
                 // not is_expr: nothing at the end to return => return Unit
@@ -162,6 +895,9 @@ impl VM {
                         ret_expr.replace(self.value_stack.pop()?);
                     }
 
+                    // locals going out of scope that were captured keep living in their upvalue
+                    self.close_upvalues(self.value_stack.len().saturating_sub(*n));
+
                     for _ in 0..*n {
                         self.value_stack.pop()?;
                     }
@@ -170,6 +906,11 @@ impl VM {
                         self.value_stack.push(val)?;
                     }
                 },  
+                OpUnwind(height) => {
+                    let height=self.frame_slots() + *height;
+                    self.close_upvalues(height);
+                    self.value_stack.truncate(height);
+                },
                 // get constant at idx in chunk, push onto stack
                 OpConstant(idx) => {
                     let i=*idx;
@@ -183,6 +924,11 @@ impl VM {
                     self.value_stack.push(get)?;
                 },
                 // if hash doesnt exist in strings, add loaded str from chunk to strings. else, loadfrom interned
+                OpBigInt(idx) => {
+                    let n=chunk.get_bigint(*idx).ok_or(errn_i!("Invalid index for big int:{}", idx))?.clone();
+                    let value=self.alloc_int(n);
+                    self.value_stack.push(value)?;
+                },
                 OpLoadString(hash) => {
                     log::debug!("Load str:{}", hash);
                     let hash=*hash;
@@ -198,22 +944,35 @@ impl VM {
                     self.value_stack.push(obj_str)?;
                 },
                 OpNegate => {
-                    let stack=&mut self.value_stack;
-                    let top=stack.pop()?.expect_int()?;
-                    stack.push(Value::num(top*-1))?;
+                    let top=self.value_stack.pop()?;
+                    let res=match top {
+                        Value::Float(f) => Value::Float(-f),
+                        Value::ObjBigInt(_) => {
+                            let n=self.expect_bigint(top)?;
+                            self.alloc_int(-n)
+                        },
+                        _ => {
+                            let n=top.expect_int()?;
+                            match n.checked_neg() {
+                                Some(n) => Value::num(n),
+                                None if self.wrapping => Value::num(n.wrapping_neg()),
+                                None => self.alloc_int(-BigInt::from(n))
+                            }
+                        }
+                    };
+                    self.value_stack.push(res)?;
                 },
                 OpAdd =>  {
                     let stack=&mut self.value_stack;
                     let right=stack.pop()?;
                     let left=stack.pop()?;
 
-                    if left.expect_int().is_ok() {
-                        let left=left.expect_int()?;
-                        let right=right.expect_int()?;
-                        stack.push(Value::num(left + right))?;
-                    } else if left.expect_string().is_ok() {
+                    if left.expect_string().is_err() {
+                        let res=arith!(left, right, +, checked_add, wrapping_add);
+                        self.value_stack.push(res)?;
+                    } else {
                         let left_hash=left.expect_string()?;
-                        let right_hash=right.expect_string()?;
+                        let right_hash=self.expect_string_value(right)?;
 
                         let left=self.strings.get_string(left_hash).unwrap();
                         let right=self.strings.get_string(right_hash).unwrap();
@@ -221,16 +980,43 @@ impl VM {
                         let res=left+right;
 
                         let hash=self.strings.add_string(res);
-                        stack.push(Value::ObjString(hash))?;
-                    } else {
-                        let msg=format!("Expected number or string but got: {}", left.to_string());
-                        return errn!(msg);
+                        self.value_stack.push(Value::ObjString(hash))?;
                     }
                 },
-                OpSub => bin_op!(-),   
-                OpMul => bin_op!(*),
-                OpDiv => bin_op!(/),   
-                OpSetGlobal(identifier) => {
+                OpSub => bin_op!(-, checked_sub, wrapping_sub),
+                OpMul => bin_op!(*, checked_mul, wrapping_mul),
+                OpDiv => bin_op!(/, checked_div, wrapping_div),
+                OpMod => bin_op!(%, checked_rem, wrapping_rem),
+                OpPow => {
+                    let right=self.value_stack.pop()?;
+                    let left=self.value_stack.pop()?;
+                    let res=self.power(left, right)?;
+                    self.value_stack.push(res)?;
+                },
+                OpBitAnd => int_op!(&),
+                OpBitOr => int_op!(|),
+                OpBitXor => int_op!(^),
+                OpShiftLeft => {
+                    let right=self.value_stack.pop()?;
+                    let left=self.value_stack.pop()?;
+                    let res=self.shift_left(left, right)?;
+                    self.value_stack.push(res)?;
+                },
+                OpShiftRight => {
+                    let right=self.value_stack.pop()?.expect_int()?;
+                    let left=self.value_stack.pop()?.expect_int()?;
+
+                    // logical: zeros are shifted in so a negative int becomes a large positive one
+                    let res=u32::try_from(right).ok().and_then(|right| (left as usize).checked_shr(right));
+                    match res {
+                        Some(res) => self.value_stack.push(Value::num(res as IntType))?,
+                        None => {
+                            let msg=format!("Shift amount {} out of range", right);
+                            return err_other!(msg);
+                        }
+                    }
+                },
+                OpSetGlobal(identifier, mutable) => {
                     log::debug!("OpSet");
                     log::debug!("{:?}", self.value_stack);        
 
@@ -239,16 +1025,37 @@ impl VM {
 
                     self.add_global(identifier.to_string(), value);
 
-                    log::debug!("Set:{:?}",self.globals);
+                    // redeclaring with let makes it immutable again
+                    let hash=calc_hash(identifier);
+                    let module=self.current_module();
+                    if *mutable {
+                        module.mutable_globals.insert(hash);
+                    } else {
+                        module.mutable_globals.remove(&hash);
+                    }
+
+                    log::debug!("Set:{:?}",module.globals);
+                },
+                OpAssignGlobal(identifier) => {
+                    self.check_assign_global(identifier)?;
+
+                    let value=self.value_stack.pop()?;
+                    self.add_global(identifier.to_string(), value);
+                },
+                OpGetAssignGlobal(identifier) => {
+                    self.check_assign_global(identifier)?;
+
+                    let value=*self.get_global(identifier).expect("Assigned global must be defined");
+                    self.value_stack.push(value)?;
                 },
                 // idx of identifier in constants
                 OpGetGlobal(ident) => {
-                    log::debug!("Get {:?} {:?} idx:{}", self.globals, chunk, ident);
+                    log::debug!("Get {:?} {:?} idx:{}", self.modules[self.module()].globals, chunk, ident);
                     let value=self.get_global(ident); // could add line num to value
 
-                    match value {
+                    match value.copied().or_else(|| find_native(ident)) {
                         Some(val) => {
-                            self.value_stack.push(val.to_owned())?;
+                            self.value_stack.push(val)?;
                         },
                         None => {
 
@@ -259,18 +1066,21 @@ impl VM {
                     }
     
                 },
+                // idx is relative to the current frame
                 OpGetLocal(idx) => {
-                    let val=self.value_stack.get(*idx).expect(format!("Bad idx for GetLocal: {}", idx).as_str());
+                    let idx=self.frame_slots() + *idx;
+                    let val=self.value_stack.get(idx).expect(format!("Bad idx for GetLocal: {}", idx).as_str());
                     debug!("Get loc:{}, item:{:?}", idx, val);
                     self.value_stack.push(val)?;
 
                 },
                 // leave value there
                 OpSetLocal(idx) => {
+                    let idx=self.frame_slots() + *idx;
                     let val=self.value_stack.peek();
                     if let Some(v) = val {
                         debug!("Set loc:{}, val:{:?}", idx, v);
-                        self.value_stack.set(*idx, *v);
+                        self.value_stack.set(idx, *v);
                     } else {
                         self.err("No value to set local variable")?;
                     }
@@ -304,13 +1114,28 @@ impl VM {
                 OpJump(idx) => {
                     self.ip=*idx;
                 },
+                &OpLoop(idx) => {
+                    self.ip=idx;
+                    continue; // idx is the start of the loop: don't skip it
+                },
                 OpTrue => self.value_stack.push(Value::Bool(true))?,
                 OpFalse => self.value_stack.push(Value::Bool(false))?,
                 OpNot => {
                     let val=self.value_stack.pop()?;
                     let val=val.expect_bool()?;
                     self.value_stack.push(Value::Bool(!val))?;
-                }
+                },
+                OpEqual | OpNotEqual => {
+                    let right=self.value_stack.pop()?;
+                    let left=self.value_stack.pop()?;
+                    let eq=self.values_equal(left, right);
+                    let res=if let OpEqual = curr { eq } else { !eq };
+                    self.value_stack.push(Value::Bool(res))?;
+                },
+                OpLess => cmp_op!(<),
+                OpLessEq => cmp_op!(<=),
+                OpGreater => cmp_op!(>),
+                OpGreaterEq => cmp_op!(>=)
             }
 
             // advance ip - may cause issue since ip advanced before match (unavoidable)
@@ -318,6 +1143,243 @@ impl VM {
         }
     }
 
+    /// Integer op for left op right given its checked, wrapping and big int versions:
+    /// small ints only use the big int op when the checked one overflows
+    fn int_arith(&mut self, left:Value, right:Value, op:&str, checked:fn(IntType, IntType)->Option<IntType>,
+        wrapping:fn(IntType, IntType)->IntType, big:fn(BigInt, BigInt)->BigInt)->Result<Value> {
+        if !matches!(left, Value::ObjBigInt(_)) && !matches!(right, Value::ObjBigInt(_)) {
+            let (l, r)=(left.expect_int()?, right.expect_int()?);
+            match checked(l, r) {
+                Some(res) => return Ok(Value::num(res)),
+                None if self.wrapping && r != 0 => return Ok(Value::num(wrapping(l, r))),
+                None => ()
+            }
+        }
+
+        let (left, right)=(self.expect_bigint(left)?, self.expect_bigint(right)?);
+        if right.is_zero() && matches!(op, "/" | "%") {
+            return err_other!("Division by zero");
+        }
+        Ok(self.alloc_int(big(left, right)))
+    }
+
+    /// left ** right: an integer to a negative power is a float e.g 2 ** -1 is 0.5
+    fn power(&mut self, left:Value, right:Value)->Result<Value> {
+        if left.is_float() || right.is_float() {
+            return Ok(Value::Float(self.expect_float(left)?.powf(self.expect_float(right)?)));
+        }
+
+        let exp=right.expect_int()?;
+        if exp < 0 {
+            return Ok(Value::Float(self.expect_float(left)?.powf(exp as f64)));
+        }
+
+        if !matches!(left, Value::ObjBigInt(_)) {
+            let base=left.expect_int()?;
+            match u32::try_from(exp).ok().and_then(|exp| base.checked_pow(exp)) {
+                Some(res) => return Ok(Value::num(res)),
+                // exponent beyond u32 can only not overflow for -1, 0 and 1: wrap by squaring instead of pow
+                None if self.wrapping || (-1..=1).contains(&base) => return Ok(Value::num(wrapping_pow(base, exp))),
+                None => ()
+            }
+        }
+
+        let base=self.expect_bigint(left)?;
+        match u32::try_from(exp) {
+            Ok(exp) => Ok(self.alloc_int(base.pow(exp))),
+            Err(_) => {
+                let msg=format!("Exponent {} is too large", exp);
+                err_other!(msg)
+            }
+        }
+    }
+
+    /// left << right: like * by a power of 2, a result that loses bits is a big int or wraps in wrapping mode
+    fn shift_left(&mut self, left:Value, right:Value)->Result<Value> {
+        let amount=right.expect_int()?;
+        let amount=match u32::try_from(amount) {
+            Ok(amount) => amount,
+            Err(_) => {
+                let msg=format!("Shift amount {} out of range", amount);
+                return err_other!(msg);
+            }
+        };
+
+        if !matches!(left, Value::ObjBigInt(_)) {
+            let l=left.expect_int()?;
+            match l.checked_shl(amount).filter(|res| res >> amount == l) {
+                Some(res) => return Ok(Value::num(res)),
+                None if self.wrapping => return Ok(Value::num(l.checked_shl(amount).unwrap_or(0))),
+                None => ()
+            }
+        }
+
+        let base=self.expect_bigint(left)?;
+        Ok(self.alloc_int(base << amount))
+    }
+
+    /// Int as a big int: bools are 0 and 1 like for expect_int
+    fn expect_bigint(&self, value:Value)->Result<BigInt> {
+        match value {
+            Value::ObjBigInt(idx) => Ok(self.heap.expect_bigint(idx)?.clone()),
+            _ => Ok(BigInt::from(value.expect_int()?))
+        }
+    }
+
+    /// Like Value::expect_float but also for big ints: those beyond f64 are infinite
+    fn expect_float(&self, value:Value)->Result<f64> {
+        match value {
+            Value::ObjBigInt(idx) => Ok(self.heap.expect_bigint(idx)?.to_f64().unwrap_or(f64::INFINITY)),
+            _ => value.expect_float()
+        }
+    }
+
+    /// Like Value::expect_string but a big int in the error shows its digits rather than <bigint>
+    fn expect_string_value(&self, value:Value)->Result<u64> {
+        match value {
+            Value::ObjBigInt(idx) => {
+                let msg=format!("Expected string but got: '{}'", self.heap.expect_bigint(idx)?);
+                err_other!(msg)
+            },
+            _ => value.expect_string()
+        }
+    }
+
+    /// Ints that fit in IntType stay a Number so that they keep using the fast path
+    fn alloc_int(&mut self, n:BigInt)->Value {
+        match n.to_isize() {
+            Some(n) => Value::num(n),
+            None => Value::ObjBigInt(self.heap.alloc_bigint(n))
+        }
+    }
+
+    /// Pop the top count values: in the order they were pushed
+    fn pop_many(&mut self, count:usize)->Vec<Value> {
+        let start=self.value_stack.len()-count;
+        let values=(start..self.value_stack.len()).map(|idx| self.value_stack.get(idx).unwrap()).collect();
+        self.value_stack.truncate(start);
+        values
+    }
+
+    fn expect_record(&self, value:Value)->Result<&Record> {
+        match value {
+            Value::ObjRecord(idx) => self.heap.expect_record(idx),
+            _ => {
+                let msg=format!("Only records have fields but got {}", value.type_name());
+                err_other!(msg)
+            }
+        }
+    }
+
+    /// Allocate a new list: for natives that return one
+    pub(crate) fn alloc_list(&mut self, items:Vec<Value>)->Value {
+        Value::ObjList(self.heap.alloc(Obj::List(items)))
+    }
+
+    /// Heap idx of list and idx as a position in it: errors if idx is out of bounds
+    fn list_index(&self, list:Value, idx:Value)->Result<(usize, usize)> {
+        let items=match list {
+            Value::ObjList(obj) => obj,
+            _ => {
+                let msg=format!("Can only index lists, tuples and maps but got {}", list.type_name());
+                return err_other!(msg);
+            }
+        };
+
+        let len=self.heap.expect_list(items)?.len();
+        Ok((items, checked_index(idx, len, "list")?))
+    }
+
+    /// Item at idx of a value that for can iterate over, None once there are no more
+    fn next_item(&self, iterable:Value, idx:IntType)->Result<Option<Value>> {
+        match iterable {
+            Value::ObjRange(obj) => {
+                let (start, end)=self.heap.expect_range(obj)?;
+                let item=start+idx;
+                Ok(if item < end { Some(Value::Number(item)) } else { None })
+            },
+            Value::ObjList(obj) => {
+                let items=self.heap.expect_list(obj)?;
+                Ok(items.get(idx as usize).copied())
+            },
+            Value::ObjTuple(obj) => {
+                let items=self.heap.expect_tuple(obj)?;
+                Ok(items.get(idx as usize).copied())
+            },
+            Value::ObjMap(obj) => {
+                let map=self.heap.expect_map(obj)?;
+                Ok(map.entries().get(idx as usize).map(|(key, _)| *key))
+            },
+            _ => {
+                let msg=format!("Cannot iterate over {}", iterable.type_name());
+                err_other!(msg)
+            }
+        }
+    }
+
+    /// Equality used by == and !=. Ints and floats compare numerically, other values of different types are never equal
+    fn values_equal(&self, left:Value, right:Value)->bool {
+        match (left, right) {
+            (Value::ObjRange(l), Value::ObjRange(r)) => self.heap.expect_range(l).ok() == self.heap.expect_range(r).ok(),
+            (Value::ObjList(l), Value::ObjList(r)) => {
+                match (self.heap.expect_list(l), self.heap.expect_list(r)) {
+                    (Ok(l), Ok(r)) => l.len()==r.len() && l.iter().zip(r.iter()).all(|(a, b)| self.values_equal(*a, *b)),
+                    _ => false
+                }
+            },
+            (Value::ObjTuple(l), Value::ObjTuple(r)) => {
+                match (self.heap.expect_tuple(l), self.heap.expect_tuple(r)) {
+                    (Ok(l), Ok(r)) => l.len()==r.len() && l.iter().zip(r.iter()).all(|(a, b)| self.values_equal(*a, *b)),
+                    _ => false
+                }
+            },
+            (Value::ObjRecord(l), Value::ObjRecord(r)) => {
+                match (self.heap.expect_record(l), self.heap.expect_record(r)) {
+                    (Ok(l), Ok(r)) => Rc::ptr_eq(&l.ty, &r.ty) && l.fields.iter().zip(r.fields.iter()).all(|(a, b)| self.values_equal(*a, *b)),
+                    _ => false
+                }
+            },
+            (Value::ObjMap(l), Value::ObjMap(r)) => {
+                match (self.heap.expect_map(l), self.heap.expect_map(r)) {
+                    (Ok(l), Ok(r)) => l.len()==r.len() && l.entries().iter().all(|(key, value)| {
+                        let other=self.heap.key(*key).ok().and_then(|key| r.get(&key));
+                        other.map(|other| self.values_equal(*value, other)).unwrap_or(false)
+                    }),
+                    _ => false
+                }
+            },
+            (Value::Float(l), Value::Float(r)) => l == r,
+            (Value::Float(f), Value::Number(n)) | (Value::Number(n), Value::Float(f)) => f == n as f64,
+            (Value::Float(f), Value::ObjBigInt(_)) => self.expect_float(right).map(|r| f == r).unwrap_or(false),
+            (Value::ObjBigInt(_), Value::Float(f)) => self.expect_float(left).map(|l| l == f).unwrap_or(false),
+            _ => left == right
+        }
+    }
+
+    /// Ordering used by <, <=, >, >=. Numbers and bools compare by value, strings lexicographically
+    fn compare_values(&self, left:Value, right:Value)->Result<Ordering> {
+        match (left, right) {
+            (Value::Number(l), Value::Number(r)) => Ok(l.cmp(&r)),
+            (Value::Number(_) | Value::ObjBigInt(_), Value::Number(_) | Value::ObjBigInt(_)) => {
+                Ok(self.expect_bigint(left)?.cmp(&self.expect_bigint(right)?))
+            },
+            (Value::Number(_) | Value::Float(_) | Value::ObjBigInt(_), Value::Number(_) | Value::Float(_) | Value::ObjBigInt(_)) => {
+                let (l, r)=(self.expect_float(left)?, self.expect_float(right)?);
+                l.partial_cmp(&r).ok_or(err_other_i!("Cannot compare NaN"))
+            },
+            (Value::Bool(l), Value::Bool(r)) => Ok(l.cmp(&r)),
+            (Value::ObjString(l), Value::ObjString(r)) => {
+                let left=self.expect_string(l)?;
+                let right=self.expect_string(r)?;
+                Ok(left.cmp(right))
+            },
+            _ => {
+                let msg=format!("Cannot compare {} with {}", left.type_name(), right.type_name());
+                err_other!(msg)
+            }
+        }
+    }
+
     /// false: don't reset for run
     pub fn interpret_with_reset(&mut self, source:&str, reset:bool)->Result<Value>{
         let mut chunk=Chunk::new();
@@ -326,17 +1388,7 @@ impl VM {
         parser.compile(&mut chunk)?;
 
         // let chunk=compile(source)?; // turn source into bytecode, consts etc
-        match self.run(&mut chunk, reset) {
-            Ok(val) => Ok(val),
-            Err(msg) => {
-                // let curr_inst=s
-                // let curr_inst=chunk.get_op(self.ip).unwrap();
-                let line=chunk.get_line_of_op(self.ip).unwrap();
-
-                let msg=format!("[line {}] {}", line, msg);
-                errn!(msg)
-            },
-        }
+        self.run(&mut chunk, reset)
     }
 
     /// Resets vm before running
@@ -352,6 +1404,72 @@ impl VM {
                 let load=load.expect("Invalid string printed: not found in VM intern");
                 format!("\"{}\"",load.to_string())
             },
+            Value::ObjMap(idx) => {
+                let entries=self.heap.expect_map(idx).expect("Invalid map printed: not found in VM heap").entries().to_vec();
+                let entries:Vec<String>=entries.into_iter()
+                    .map(|(key, value)| format!("{}: {}", self.print_value(key), self.print_value(value)))
+                    .collect();
+                format!("#{{{}}}", entries.join(", "))
+            },
+            Value::Native(idx) => format!("<native fn {}>", NATIVES[idx].name),
+            Value::ObjBigInt(idx) => self.heap.expect_bigint(idx).expect("Invalid big int printed: not found in VM heap").to_string(),
+            Value::ObjList(idx) => {
+                let items=self.heap.expect_list(idx).expect("Invalid list printed: not found in VM heap").clone();
+                let items:Vec<String>=items.into_iter().map(|item| self.print_value(item)).collect();
+                format!("[{}]", items.join(", "))
+            },
+            Value::ObjTuple(idx) => {
+                let items=self.heap.expect_tuple(idx).expect("Invalid tuple printed: not found in VM heap").clone();
+                let items:Vec<String>=items.into_iter().map(|item| self.print_value(item)).collect();
+
+                // (1,) so that a 1-tuple reads differently from a grouping
+                if items.len()==1 {
+                    format!("({},)", items[0])
+                } else {
+                    format!("({})", items.join(", "))
+                }
+            },
+            Value::ObjRecord(idx) => {
+                let record=self.heap.expect_record(idx).expect("Invalid record printed: not found in VM heap").clone();
+                let ty=record.ty;
+
+                // variants print like the call that made them e.g Rect(1, 2)
+                if ty.sum.is_some() {
+                    let fields:Vec<String>=record.fields.into_iter().map(|value| self.print_value(value)).collect();
+                    return if fields.is_empty() {
+                        ty.name.clone()
+                    } else {
+                        format!("{}({})", ty.name, fields.join(", "))
+                    };
+                }
+
+                let fields:Vec<String>=ty.fields.iter().zip(record.fields)
+                    .map(|(name, value)| format!("{}: {}", name, self.print_value(value)))
+                    .collect();
+
+                if fields.is_empty() {
+                    format!("{} {{}}", ty.name)
+                } else {
+                    format!("{} {{ {} }}", ty.name, fields.join(", "))
+                }
+            },
+            Value::ObjType(idx) => {
+                let ty=self.heap.expect_type(idx).expect("Invalid type printed: not found in VM heap");
+                format!("<type {}>", ty.name)
+            },
+            Value::ObjModule(idx) => format!("<module {}>", self.modules[idx].name),
+            Value::ObjRange(idx) => {
+                let (start, end)=self.heap.expect_range(idx).expect("Invalid range printed: not found in VM heap");
+                format!("{}..{}", start, end)
+            },
+            Value::ObjClosure(idx) => {
+                let function=&self.heap.expect_closure(idx).expect("Invalid function printed: not found in VM heap").function;
+                if function.name.is_empty() {
+                    String::from("<fn>")
+                } else {
+                    format!("<fn {}>", function.name)
+                }
+            },
             _ => value.to_string()
         }
     }
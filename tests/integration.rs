@@ -1,5 +1,5 @@
 use nova::vm;
-use nova::{get_output, test_input, test_input_many, 
+use nova::{get_output, test_input, test_input_many, test_input_many_with_prefix,
     vm::VM, parser::parser::Parser, data::ops::Chunk,
     utils::file::run_file
 };
@@ -21,23 +21,188 @@ fn test_binary_ops() {
     test_input_many(&v);
 }
 
+#[test]
+fn test_comparison() {
+    let v = vec![
+        ("1 < 2", "true"),
+        ("2 <= 2", "true"),
+        ("3 > 4", "false"),
+        ("4 >= 5", "false"),
+        ("1 == 1", "true"),
+        ("1 != 1", "false"),
+        ("1 + 2 < 2 * 2", "true"),
+        ("1 < 2 == 3 < 4", "true"),
+        ("false < true", "true"),
+        ("true == true", "true"),
+        ("\"abc\" < \"abd\"", "true"),
+        ("\"b\" >= \"abc\"", "true"),
+        ("\"abc\" == \"ab\" + \"c\"", "true"),
+        ("1 == true", "false"),
+        ("\"1\" != 1", "true"),
+        ("let x = 5; x < 10", "true"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("1 < \"a\"").contains("Cannot compare number with string"));
+}
+
+#[test]
+fn test_logical() {
+    let v = vec![
+        ("true and true", "true"),
+        ("true and false", "false"),
+        ("false or true", "true"),
+        ("false or false", "false"),
+        ("1 < 2 and 3 < 4", "true"),
+        ("1 > 2 or 3 < 4 and 4 < 3", "false"),
+        ("true or false and false", "true"),
+        ("false and true or true", "true"),
+        ("let x = 0; x != 0 and 10 / x > 1", "false"),
+        ("let x = 5; x != 0 and 10 / x > 1", "true"),
+        ("let x = 0; x == 0 or 10 / x > 1", "true"),
+        ("2 and 3", "3"),
+        ("0 or 4", "4"),
+        ("!(false or false)", "true"),
+    ];
+    test_input_many(&v);
+
+    // right side is not evaluated when the left side decides the result
+    assert!(get_output("false and y").contains("false"));
+    assert!(get_output("true or y").contains("true"));
+    assert!(get_output("true and y").contains("'y' is not defined"));
+}
+
+#[test]
+fn test_float() {
+    let v = vec![
+        ("700.30", "700.3"),
+        ("2.0", "2.0"),
+        ("1.5 + 1.5", "3.0"),
+        ("1 + 0.5", "1.5"),
+        ("0.5 * 4", "2.0"),
+        ("7 / 2", "3"),
+        ("7 / 2.0", "3.5"),
+        ("10 - 0.25", "9.75"),
+        ("-2.5", "-2.5"),
+        ("-(1.5 - 3)", "1.5"),
+        ("1.0 == 1", "true"),
+        ("2 > 1.5", "true"),
+        ("0.1 + 0.2 < 0.31", "true"),
+        ("let x = 2.5; let y = 2; x * y", "5.0"),
+    ];
+    test_input_many(&v);
+}
+
+#[test]
+fn test_functions() {
+    let v = vec![
+        ("fun add(a, b) { a + b } add(1, 2)", "3"),
+        ("fun f(n) { n * 2 } f(2) + f(3)", "10"),
+        ("fun fib(n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); } fib(15)", "610"),
+        ("let sq = fun (x) { x * x }; sq(sq(2))", "16"),
+        ("fun (x) { x + 1 }(1)", "2"),
+        ("fun f() { 1 } f", "<fn f>"),
+        ("fun f() { 1 }; f()", "1"),
+        ("{ fun g(x) { x * 3 }; g(2) }", "6"),
+        ("fun (x) { x }", "<fn>"),
+        ("fun f() { let x = 1; } f()", "()"),
+        ("fun f() { return; } f()", "()"),
+        ("fun f(x) { if (x > 0) { return \"pos\"; } \"nonpos\" } f(-1)", "\"nonpos\""),
+        ("fun f(x) { let y = x + 1; { let z = y * 2; z } } 1 + f(2)", "7"),
+        ("fun apply(g, x) { g(x) } apply(fun (x) { x - 1 }, 10)", "9"),
+        ("let x = 5; fun f() { x * 2 } f()", "10"),
+        ("fun f(a) { a } f(1); f(2)", "2"),
+        ("(1) + (2)", "3"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("fun f(a) { a } f(1, 2)").contains("Expected 1 arguments but got 2"));
+    assert!(get_output("let n = 2; n(3)").contains("Can only call functions but got number"));
+    // a literal is never called: (3) starts a new expression
+    assert!(get_output("2(3)").contains("Expressions not allowed immediately after another expression"));
+    assert!(get_output("return 2;").contains("Can't return from top-level code"));
+    assert!(get_output("fun f(n) { f(n + 1) } f(0)").contains("Maximum call depth 256 exceeded"));
+    let items=vec!["1"; 5000].join(", ");
+    assert!(get_output(&format!("[{}]", items)).contains("stack overflow"));
+}
+
+#[test]
+fn test_closures() {
+    let counter="fun make_counter() { let mut c = 0; fun inc() { c = c + 1; c } inc }";
+    let v = vec![
+        (format!("{} let a = make_counter(); a(); a(); a()", counter), "3"),
+        (format!("{} let a = make_counter(); let b = make_counter(); a(); a(); b()", counter), "1"),
+        ("fun add(x) { fun (y) { x + y } } add(1)(2)".to_string(), "3"),
+        ("fun outer() { let x = \"outer\"; fun middle() { fun inner() { x } inner } middle()() } outer()".to_string(), "\"outer\""),
+        ("fun f() { let mut x = 1; fun get() { x } fun set(v) { x = v; } set(10); get() } f()".to_string(), "10"),
+        ("let f = { let y = 5; fun g() { y } g }; f()".to_string(), "5"),
+        ("fun f() { fun fact(n) { if (n < 2) { return 1; } n * fact(n - 1) } fact(5) } f()".to_string(), "120"),
+    ];
+    let v:Vec<(&str, &str)>=v.iter().map(|(i, o)| (i.as_str(), *o)).collect();
+    test_input_many(&v);
+}
+
+#[test]
+fn test_lambdas() {
+    let v = vec![
+        ("let inc = x -> x + 1; inc(1)", "2"),
+        ("let add = x, y -> x + y; add(2, 3)", "5"),
+        ("let f = (a, b) -> { let c = a * b; c + 1 }; f(3, 4)", "13"),
+        ("let k = () -> 42; k()", "42"),
+        ("(x -> x * 2)(4)", "8"),
+        ("x -> x", "<fn>"),
+        ("fun apply(g, v) { g(v) } apply(x -> x * 10, 5)", "50"),
+        ("fun apply(g, v) { g(v) } apply((x) -> x - 1, 5)", "4"),
+        ("fun adder(n) { x -> x + n } adder(3)(4)", "7"),
+        ("let compose = f, g -> x -> f(g(x)); compose(x -> x * 2, x -> x + 1)(3)", "8"),
+        // inside an argument list a comma separates arguments
+        ("fun f(a, g) { g(a) } f(2, b -> b * 3)", "6"),
+        ("let x = 3; (x)", "3"),
+    ];
+    test_input_many(&v);
+}
+
+#[test]
+fn test_pipe() {
+    let fns="fun double(x) { x * 2 } fun add(a, b) { a + b } fun sub(a, b) { a - b }";
+    let v = vec![
+        ("3 >> double", "6"),
+        ("3 >> double >> double", "12"),
+        ("3 >> add(4)", "7"),
+        ("10 >> sub(4)", "6"),
+        ("3 >> add(4) >> double", "14"),
+        ("1 + 2 >> double", "6"),
+        ("5 >> (x -> x - 1)", "4"),
+        ("5 >> x -> x - 1", "4"),
+        ("fun mk(n) { x -> x * n } 2 >> (mk(5))", "10"),
+    ];
+    test_input_many_with_prefix(fns, &v);
+
+    assert!(get_output("fun f(x) { x } 1 >> f(2)").contains("Expected 1 arguments but got 2"));
+}
+
 #[test]
 fn test_assignment() {
     // test_input("x=5", "2");
-    let inp="x=5;";
+    let inp="let x=5;";
     let mut vm=VM::new();
     let res=vm.interpret_with_reset(inp, false);
     assert_eq!(res.unwrap().to_string(), "()");
 
     assert_eq!(vm.get_global_value("x").unwrap().to_string(), "5");
 
+    // only let declares
+    let res=vm.interpret_with_reset("w=5;", false);
+    assert!(res.unwrap_err().to_string().contains("[line 1] Cannot assign to undeclared variable 'w'"));
+    assert!(vm.get_global_value("w").is_none());
 
-    let res=vm.interpret_with_reset("let x = 10;", false);
+
+    let res=vm.interpret_with_reset("let mut x = 10;", false);
     assert_eq!(res.unwrap().to_string(), "()"); // no return
 
     assert_eq!(vm.get_global_value("x").unwrap().to_string(), "10");
 
-    let res=vm.interpret_with_reset("x=20; y=30; x+y", false);
+    let res=vm.interpret_with_reset("x=20; let y=30; x+y", false);
     assert_eq!(res.unwrap().to_string(), "50");
 
     let code="let x = (10+20)*30;  let y=30+50; x+(y*2+3)";
@@ -123,4 +288,610 @@ fn test_file() {
 fn test_if() {
     output_has("if.txt", "2\n2\n3\n4\n5\n6\n\"end\"\n");
     output_has("if2.txt", "20\n\"x\"\n2\n70\n80\n60\n50\n\"z\"\n10\n50\n");
-}
\ No newline at end of file
+}
+#[test]
+fn test_infix() {
+    let fns="fun max(a, b) { if (a > b) { return a; } b } fun sub(a, b) { a - b }";
+    let v = vec![
+        ("3 $max 5", "5"),
+        ("10 $ sub 1", "9"),
+        // undeclared operators bind like * and are left associative
+        ("1 + 3 $max 5", "6"),
+        ("10 $sub 3 $sub 2", "5"),
+        ("infixr 5 $sub; 10 $sub 3 $sub 2", "9"),
+        ("infixl 5 $sub; 1 + 10 $sub 3", "8"),
+        ("infixl 1 $max; 1 < 2 $max false", "true"),
+        ("infixl 6 $pow; fun pow(a, b) { if (b == 0) { return 1; } a * pow(a, b - 1) } 2 $pow 3 + 1", "9"),
+    ];
+    test_input_many_with_prefix(fns, &v);
+
+    assert!(get_output("infixl 7 $f;").contains("Infix precedence must be between 1 and 6"));
+}
+
+#[test]
+fn test_while() {
+    let v = vec![
+        ("let mut i = 0; let mut sum = 0; while (i < 5) { let sq = i * i; sum = sum + sq; i = i + 1; } sum", "30"),
+        ("fun count(n) { let mut c = 0; while (c < n) { c = c + 1; } c } count(7)", "7"),
+        ("let mut n = 0; while (false) { n = 1; } n", "0"),
+        // body locals are popped each iteration
+        ("let mut i = 0; while (i < 100) { let a = i; let b = a + 1; i = b; } i", "100"),
+        ("let mut i = 0; while (i < 3) { i = i + 1; i } i", "3"),
+        ("let mut i = 0; let mut x = 0; while (i < 3) { let mut j = 0; while (j < 3) { x = x + 1; j = j + 1; } i = i + 1; } x", "9"),
+        // closures capture the variable of their own iteration
+        ("let mut f = 0; let mut k = 0; while (k < 3) { let j = k; k = k + 1; f = fun () { j }; } f()", "2"),
+        ("{ let mut x = 1; { x = 2; } x }", "2"),
+        // values left by an if branch are dropped each iteration
+        ("let mut i = 0; while (i < 3000) { if (true) { 1 } i += 1; } i", "3000"),
+        ("let mut i = 0; while (i < 3000) { if (false) { 1 } else { 2 } i += 1; } i", "3000"),
+        ("let mut i = 0; let r = while (true) { i += 1; if (true) { 1 } if (i == 2500) { break i * 2; } }; r", "5000"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("while (\"a\") { }").contains("Expected bool but got a string"));
+}
+
+#[test]
+fn test_for() {
+    let v = vec![
+        ("let mut sum = 0; for i in 0..5 { sum = sum + i; } sum", "10"),
+        ("let n = 3; let mut p = 1; for i in 1..n+1 { p = p * i; } p", "6"),
+        ("fun f() { let mut acc = 0; for i in 0..10 { let d = i * 2; acc = acc + d; } acc } f()", "90"),
+        ("let mut c = 0; for i in 5..2 { c = c + 1; } c", "0"),
+        ("let r = 2..4; let mut s = 0; for x in r { for y in r { s = s + x * y; } } s", "25"),
+        ("let i = 10; for i in 0..3 { } i", "10"),
+        // each iteration has its own loop variable
+        ("let mut fs = 0; for i in 0..3 { fs = fun () { i }; } fs()", "2"),
+        // values left by an if branch are dropped each iteration
+        ("let mut n = 0; for i in 0..3000 { if (true) { 1 } n += 1; } n", "3000"),
+        ("let mut n = 0; for i in 0..3000 { if (i < 0) { 1 } else { 2 } n += 1; } n", "3000"),
+        ("1..3", "1..3"),
+        ("1..3 == 1..3", "true"),
+        ("0..1 + 1", "0..2"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("for x in 3 { }").contains("Cannot iterate over number"));
+    assert!(get_output("1.5..3").contains("Expected integer but got a float"));
+}
+
+#[test]
+fn test_break_continue() {
+    let v = vec![
+        ("let found = for i in 0..100 { if (i * i > 50) { break i; } }; found", "8"),
+        ("let mut s = 0; for i in 0..10 { if (i == 5) { continue; } let d = i; s = s + d; } s", "40"),
+        ("let mut i = 0; let w = while (true) { i = i + 1; let a = i * 2; { let b = a; if (b > 6) { break b; } } }; w", "8"),
+        ("for i in 0..3 { }", "()"),
+        ("let x = for i in 0..3 { if (i == 1) { break; } }; x", "()"),
+        ("fun find(xs, n) { for x in xs { if (x == n) { break x * 100; } } } find(0..10, 7)", "700"),
+        ("fun find(xs, n) { for x in xs { if (x == n) { break x * 100; } } } find(0..10, 20)", "()"),
+        ("let mut t = 0; for i in 0..3 { for j in 0..3 { if (j == 1) { break; } t = t + 1; } } t", "3"),
+        ("let mut c = 0; let mut k = 0; while (k < 10) { k = k + 1; if (k > 3) { continue; } let z = k; c = c + z; } c", "6"),
+        ("fun f() { for x in 0..5 { let g = fun () { x }; if (x == 2) { break g; } } } f()()", "2"),
+        // locals outside the loop are untouched by unwinding
+        ("{ let q = 1; for x in 0..3 { let y = x; if (x == 1) { continue; } } q }", "1"),
+        ("let mut n = 0; for i in 0..10 { n = n + 1; } n", "10"),
+        // operands already pushed when break or continue runs are popped too
+        ("let r = for i in 0..3 { let x = [1, 2, { break 5; }]; }; r", "5"),
+        ("fun f(a, b) { a } let r = for i in 0..3 { let x = f(1, { break 7; }); }; r", "7"),
+        ("let r = for i in 0..3 { let t = (1, { break 9; }); }; r", "9"),
+        ("fun g() { let a = 10; let r = for i in 0..3 { let x = [a, { break 1; }]; }; let b = 20; (a, r, b) } g()", "(10, 1, 20)"),
+        ("let mut s = 0; for i in 0..4 { let x = [i, { if (i % 2 == 0) { continue; } i }]; s += x[1]; } s", "4"),
+        ("fun f(a, b) { a + b } let mut s = 0; for i in 0..3 { s += f(i, { if (i == 1) { continue; } 10 }); } s", "22"),
+        ("fun h() { let mut n = 0; for i in 0..3 { let t = (n, { if (i < 2) { continue; } i }); n += t[1]; } n } h()", "2"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("break;").contains("Can't break outside of a loop"));
+    assert!(get_output("continue;").contains("Can't continue outside of a loop"));
+    assert!(get_output("while (true) { fun f() { break; } }").contains("Can't break outside of a loop"));
+}
+
+#[test]
+fn test_lists() {
+    let v = vec![
+        ("[1, 2, 3]", "[1, 2, 3]"),
+        ("[]", "[]"),
+        ("[1, \"a\", 1.5, [true]]", "[1, \"a\", 1.5, [true]]"),
+        ("let xs = [1, 2, 3]; xs[0] + xs[2]", "4"),
+        ("let xs = [1, 2, 3]; xs[1] = 20; xs", "[1, 20, 3]"),
+        ("let i = 1; let xs = [1, 2, 3]; xs[i + 1]", "3"),
+        // lists are shared, not copied
+        ("let xs = [1, 2]; let ys = xs; ys[0] = 5; xs", "[5, 2]"),
+        ("[[1, 2], [3]][0][1]", "2"),
+        ("let m = [[0, 0], [0, 0]]; m[1][0] = 5; m", "[[0, 0], [5, 0]]"),
+        ("let mut s = 0; for x in [1, 2, 3,] { s = s + x; } s", "6"),
+        ("[1, 2] == [1, 2]", "true"),
+        ("[1, 2] == [2, 1]", "false"),
+        ("fun f() { let l = [1]; l[0] = l[0] + 1; l } f()", "[2]"),
+        ("fun f(x) { x * 2 } [f(1), f(2)]", "[2, 4]"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("[1, 2, 3][3]").contains("Index 3 out of bounds for list of length 3"));
+    assert!(get_output("let xs = [1]; xs[-1] = 2;").contains("Index -1 out of bounds for list of length 1"));
+    assert!(get_output("5[0]").contains("Can only index lists, tuples and maps but got number"));
+    assert!(get_output("[1][\"a\"]").contains("Expected number but got a string"));
+}
+
+#[test]
+fn test_maps() {
+    let v = vec![
+        ("#{\"a\": 1, \"b\": 2}", "#{\"a\": 1, \"b\": 2}"),
+        ("#{}", "#{}"),
+        ("let m = #{\"a\": 1, \"b\": 2}; m[\"a\"] + m[\"b\"]", "3"),
+        ("let m = #{\"a\": 1}; m[\"b\"] = 2; m[\"a\"] = 10; m", "#{\"a\": 10, \"b\": 2}"),
+        ("let m = #{\"a\": 1}; has(m, \"a\")", "true"),
+        ("let m = #{\"a\": 1}; has(m, \"b\")", "false"),
+        ("keys(#{\"x\": 1, \"y\": 2})", "[\"x\", \"y\"]"),
+        ("values(#{\"x\": 1, \"y\": 2})", "[1, 2]"),
+        ("let m = #{\"x\": 1, \"y\": 2, \"z\": 3}; remove(m, \"y\"); m", "#{\"x\": 1, \"z\": 3}"),
+        ("let m = #{\"x\": 1}; remove(m, \"x\")", "1"),
+        ("let m = #{\"x\": 1}; remove(m, \"y\")", "()"),
+        ("len(#{1: 2, 3: 4})", "2"),
+        ("len([1, 2, 3])", "3"),
+        ("len(\"hello\")", "5"),
+        ("let t = #{1: \"one\", true: \"yes\", 1.5: \"float\"}; [t[1], t[true], t[1.5]]", "[\"one\", \"yes\", \"float\"]"),
+        ("let mut s = \"\"; for k in #{\"x\": 1, \"y\": 2} { s = s + k; } s", "\"xy\""),
+        ("#{\"a\": [1]} == #{\"a\": [1]}", "true"),
+        ("#{\"a\": 1} == #{\"a\": 2}", "false"),
+        ("let m = #{\n  \"apples\": 3,\n  \"pears\": 0,\n}; m[\"apples\"]", "3"),
+        ("len", "<native fn len>"),
+        // globals shadow natives
+        ("let len = 5; len", "5"),
+        // keys equal by == find the same entry
+        ("#{(1, 2): \"t\"}[(1, 2)]", "\"t\""),
+        ("let m = #{1: \"one\"}; [m[1.0], has(m, 1.0)]", "[\"one\", true]"),
+        ("let m = #{2.0: \"two\"}; m[2] = \"TWO\"; m", "#{2.0: \"TWO\"}"),
+        ("#{2 ** 70: \"big\"}[2 ** 70]", "\"big\""),
+        ("#{(\"a\", (1, 2)): 5}[(\"a\", (1, 2.0))]", "5"),
+        ("#{1..3: \"r\"}[1..3]", "\"r\""),
+        ("type Point { x, y } #{Point { x: 1, y: 2 }: 1}[Point { x: 1, y: 2 }]", "1"),
+        ("type P { x } type Q { x } has(#{P { x: 1 }: 1}, Q { x: 1 })", "false"),
+        ("let m = #{(1, 2): 1, (3, 4): 2}; remove(m, (1, 2)); m", "#{(3, 4): 2}"),
+        ("#{(1, 2): 1} == #{(1, 2): 1}", "true"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("#{\"a\": 1}[\"b\"]").contains("Key \"b\" not found in map"));
+    assert!(get_output("keys([1])").contains("Expected map but got list"));
+    assert!(get_output("#{[1, 2]: \"a\"}").contains("Cannot use a list as a map key: it can change after being added"));
+    assert!(get_output("let m = #{}; m[#{}] = 1;").contains("Cannot use a map as a map key"));
+    assert!(get_output("has(#{1: 2}, [1])").contains("Cannot use a list as a map key"));
+    assert!(get_output("len(1, 2)").contains("Expected 1 arguments but got 2"));
+}
+
+#[test]
+fn test_tuples() {
+    let v = vec![
+        ("(1, \"a\", true)", "(1, \"a\", true)"),
+        ("(1,)", "(1,)"),
+        ("()", "()"),
+        ("(1 + 2) * 3", "9"),
+        ("(1, (2, 3))[1][0]", "2"),
+        ("let t = (1, 2, 3); len(t)", "3"),
+        ("(1, [2]) == (1, [2])", "true"),
+        ("(1, 2) == (2, 1)", "false"),
+        ("let mut s = 0; for x in (1, 2, 3) { s = s + x; } s", "6"),
+        ("let (a, b) = (1, 2); a * 10 + b", "12"),
+        ("fun divmod(x, y) { (x / y, x - x / y * y) } let (q, r) = divmod(17, 5); (q, r)", "(3, 2)"),
+        ("{ let (a, b) = (\"x\", \"y\"); let c = 3; (b, a, c) }", "(\"y\", \"x\", 3)"),
+        ("fun f() { let (a, b) = (1, 2); () -> a + b } f()()", "3"),
+        ("fun add((x, y), z) { x + y + z } add((1, 2), 3)", "6"),
+        ("fun swap((a, b)) { (b, a) } swap((1, 2))", "(2, 1)"),
+        // elements are never bare lambda params
+        ("let a = 1; (a, b -> b)[0]", "1"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("let (a, b) = (1, 2, 3);").contains("Expected tuple of 2 items but got 3"));
+    assert!(get_output("let (a, b) = [1, 2];").contains("Cannot destructure list as a tuple"));
+    assert!(get_output("let t = (1, 2); t[0] = 5;").contains("Cannot assign to an item of a tuple"));
+    assert!(get_output("(1, 2)[2]").contains("Index 2 out of bounds for tuple of length 2"));
+}
+
+#[test]
+fn test_match() {
+    let v = vec![
+        ("fun sign(x) { match x { 0 => \"zero\", n if n < 0 => \"neg\", _ => \"pos\" } } [sign(0), sign(-2), sign(7)]", "[\"zero\", \"neg\", \"pos\"]"),
+        ("match \"b\" { \"a\" => 1, \"b\" => 2, _ => 3 }", "2"),
+        ("match -1 { -1 => true, _ => false }", "true"),
+        ("match 1.5 { 1.5 => \"f\", _ => \"g\" }", "\"f\""),
+        ("match () { () => \"unit\" }", "\"unit\""),
+        ("match (1, \"a\") { (0, _) => 0, (n, \"a\") => n + 10 }", "11"),
+        ("match (1, (2, 3)) { (a, (b, c)) => a + b + c }", "6"),
+        ("match [1, 2] { [] => 0, [a] => a, [a, b] => a * b }", "2"),
+        ("match [1, 2, 3] { [h, ..t] => (h, t) }", "(1, [2, 3])"),
+        ("match [1] { [h, ..] => h }", "1"),
+        ("fun sum(xs) { match xs { [] => 0, [h, ..t] => h + sum(t) } } sum([1, 2, 3, 4])", "10"),
+        // guard failing moves on to the next arm
+        ("match (2, 3) { (a, b) if a > b => a, (a, b) => b }", "3"),
+        ("match 3 { n => { let m = n * 2; m + 1 } }", "7"),
+        ("match 3 { _ => { let z = 1; } }", "()"),
+        ("let x = 2; let y = match x { 1 => \"one\", 2 => \"two\" }; y", "\"two\""),
+        ("1 + match 2 { n => n * 10 }", "21"),
+        ("[match 1 { 1 => \"a\" }, match 2 { _ => \"b\" }]", "[\"a\", \"b\"]"),
+        ("fun f(p) { match p { (a, b) => () -> a + b } } f((1, 2))()", "3"),
+        ("let mut s = 0; for i in 0..10 { match i { 4 => { break; }, n => { s = s + n; } } } s", "6"),
+        ("let mut r = 0; match 1 { 1 => { r = 5; }, _ => () } r", "5"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("match 3 { 1 => 1, 2 => 2 }").contains("No match arm matched 3"));
+    assert!(get_output("match [1] { (a, b) => a }").contains("No match arm matched [1]"));
+    assert!(get_output("match 1 { + => 1 }").contains("Expected pattern but got"));
+}
+
+#[test]
+fn test_let_expressions() {
+    let v = vec![
+        ("3 + (let y 2)", "5"),
+        ("3 + (let y 2 y)", "5"),
+        ("(let x 3) + (let y 2)", "5"),
+        ("let x = 1; (x + (let x 20 (x + 30)))", "51"),
+        ("let x = 1; (let x 20 x); x", "1"),
+        ("(let a 1 b 2 a + b)", "3"),
+        ("(let a 1 b a + 1 b * 10)", "20"),
+        ("(let f fun (n) { n * 2 } f(4))", "8"),
+        ("fun g(v) { (let w v * 2 (w, v)) } g(5)", "(10, 5)"),
+        ("(let inc x -> x + 1 inc)(1)", "2"),
+        ("(let xs [1, 2] xs)[1]", "2"),
+        ("fun f() { let a = 1; (let b 2 () -> a + b) } f()()", "3"),
+        ("[(let a 1), (let b 2 b + 1)]", "[1, 3]"),
+        ("(let a 1 b (a + 1) b)", "2"),
+        ("(let a 1 b [a, 2] c (b, a) c)", "([1, 2], 1)"),
+        ("(let a -1 b -a b)", "1"),
+        ("(let a 5 b -1 a + b)", "4"),
+        ("(let a 5 a - 1)", "4"),
+        ("(let f x -> x * 2 f(3))", "6"),
+        ("(let xs [4, 5] xs[1])", "5"),
+    ];
+    test_input_many(&v);
+
+    // bindings never leak into globals
+    assert!(get_output("(let q 1); q").contains("Variable 'q' is not defined"));
+    assert!(get_output("(let 1 2)").contains("Expected identifier"));
+}
+
+#[test]
+fn test_string_escapes() {
+    let v = vec![
+        ("\"a\\tb\"", "\"a\tb\""),
+        ("\"line\\nnext\"", "\"line\nnext\""),
+        ("\"say \\\"hi\\\"\"", "\"say \"hi\"\""),
+        ("\"back\\\\slash\"", "\"back\\slash\""),
+        ("\"\\{not interpolated\\}\"", "\"{not interpolated}\""),
+        ("len(\"\\\"\\n\")", "2"),
+        ("len(\"héllo\")", "5"),
+        ("match \"a\\\"b\" { \"a\\\"b\" => 1, _ => 2 }", "1"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("\"\\q\"").contains("Unknown escape sequence '\\q' in string"));
+}
+
+#[test]
+fn test_string_interpolation() {
+    let v = vec![
+        ("let name = \"Nova\"; \"hello {name}!\"", "\"hello Nova!\""),
+        ("\"{1 + 2} = three\"", "\"3 = three\""),
+        ("let x = 1; \"{x}\"", "\"1\""),
+        ("\"{[1, 2]} {(1, \"x\")} {#{1: true}}\"", "\"[1, 2] (1, \"x\") #{1: true}\""),
+        ("fun f(x) { let y = x * 2; \"x={x}, y={y}\" } f(3)", "\"x=3, y=6\""),
+        ("let name = \"a\"; \"outer {\"inner {name}\"}\"", "\"outer inner a\""),
+        ("let xs = [\"a\", \"b\"]; \"{xs[0]}{xs[1]}\"", "\"ab\""),
+        ("\"{fun (x) { x }}\"", "\"<fn>\""),
+        ("let mut s = \"\"; for i in 0..3 { s = \"{s}{i}\"; } s", "\"012\""),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("\"{}\"").contains("Expected expression in string interpolation"));
+    assert!(get_output("\"{1\"").contains("Expected '}' after interpolated expression"));
+    assert!(get_output("\"{1 2}\"").contains("Expected '}' after interpolated expression"));
+    assert!(get_output("match \"a\" { \"{x}\" => 1 }").contains("Can't interpolate in a pattern"));
+}
+
+#[test]
+fn test_arithmetic_ops() {
+    let v = vec![
+        ("7 % 3", "1"),
+        ("-7 % 3", "-1"),
+        ("7.5 % 2", "1.5"),
+        ("2 + 7 % 4", "5"),
+        ("2 ** 10", "1024"),
+        // right associative and tighter than unary minus on its left
+        ("2 ** 3 ** 2", "512"),
+        ("-2 ** 2", "-4"),
+        ("2 * 3 ** 2", "18"),
+        ("2 ** -1", "0.5"),
+        ("4.0 ** 0.5", "2.0"),
+        ("6 & 3", "2"),
+        ("6 | 3", "7"),
+        ("6 ^ 3", "5"),
+        ("1 << 4", "16"),
+        ("256 >>> 2", "64"),
+        ("-16 >>> 2", "4611686018427387900"),
+        ("-1 >>> 1", "9223372036854775807"),
+        ("-1 >>> 63", "1"),
+        ("-3 << 2", "-12"),
+        // << promotes to a big int like * instead of losing bits
+        ("1 << 62", "4611686018427387904"),
+        ("1 << 63", "9223372036854775808"),
+        ("-1 << 63", "-9223372036854775808"),
+        ("-1 << 64", "-18446744073709551616"),
+        ("3 << 100", "3802951800684688204490109616128"),
+        ("(1 << 70) << 2", "4722366482869645213696"),
+        ("(1 << 64) == 2 ** 64", "true"),
+        ("1 + 2 << 1", "6"),
+        ("1 | 6 & 3", "3"),
+        ("1 | 2 == 3", "true"),
+        ("let mut h = 0; for c in [1, 2, 3] { h = (h << 5) ^ c; } h", "1091"),
+        // >> stays the pipe
+        ("[1, 2] >> len", "2"),
+        ("infixl 6 $add; fun add(a, b) { a + b } 1 $add 2 * 3", "7"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("1.5 & 1").contains("Expected integer but got a float"));
+    assert!(get_output("1 << -1").contains("Shift amount -1 out of range"));
+    assert!(get_output("1 >>> 64").contains("Shift amount 64 out of range"));
+    assert!(get_output("1 >>> -1").contains("Shift amount -1 out of range"));
+    assert!(get_output("(1 << 64) >>> 1").contains("Integer too large"));
+}
+
+#[test]
+fn test_checked_arithmetic() {
+    let v = vec![
+        ("9223372036854775807 - 1", "9223372036854775806"),
+        ("-9223372036854775807 - 1", "-9223372036854775808"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("1 / 0").contains("Division by zero"));
+    assert!(get_output("1 % 0").contains("Division by zero"));
+    assert!(get_output("1.0 / 0").contains("inf"));
+    assert!(get_output("let x = 1;\n x / 0").contains("[line 2] Division by zero"));
+
+    let mut vm=VM::new();
+    vm.set_wrapping(true);
+    let res=vm.interpret("9223372036854775807 + 1").unwrap();
+    assert_eq!(vm.print_value(res), "-9223372036854775808");
+    let res=vm.interpret("2 ** 64").unwrap();
+    assert_eq!(vm.print_value(res), "0");
+    let res=vm.interpret("1 << 63").unwrap();
+    assert_eq!(vm.print_value(res), "-9223372036854775808");
+    let res=vm.interpret("3 << 64").unwrap();
+    assert_eq!(vm.print_value(res), "0");
+    assert!(vm.interpret("1 / 0").unwrap_err().to_string().contains("Division by zero"));
+}
+
+#[test]
+fn test_big_ints() {
+    let v = vec![
+        ("9223372036854775807 + 1", "9223372036854775808"),
+        ("-9223372036854775807 - 2", "-9223372036854775809"),
+        ("9223372036854775807 * 9223372036854775807", "85070591730234615847396907784232501249"),
+        ("2 ** 100", "1267650600228229401496703205376"),
+        ("(-2) ** 65", "-36893488147419103232"),
+        ("let m = -9223372036854775807 - 1; -m", "9223372036854775808"),
+        ("(-9223372036854775807 - 1) / -1", "9223372036854775808"),
+        // literals too large for 64 bits
+        ("123456789012345678901234567890", "123456789012345678901234567890"),
+        ("-123456789012345678901234567890 + 1", "-123456789012345678901234567889"),
+        ("100000000000000000000 % 7", "2"),
+        ("100000000000000000000 / 3", "33333333333333333333"),
+        ("-100000000000000000000 / 3", "-33333333333333333333"),
+        // results that fit go back to being ints
+        ("(9223372036854775807 + 1) - 1", "9223372036854775807"),
+        ("2 ** 64 / 2 ** 60", "16"),
+        ("(2 ** 64 - 2 ** 64) == 0", "true"),
+        ("2 ** 64 == 2 ** 64", "true"),
+        ("2 ** 64 == 2 ** 65", "false"),
+        ("2 ** 64 == 18446744073709551616.0", "true"),
+        ("2 ** 64 > 9223372036854775807", "true"),
+        ("-(2 ** 64) < 2 ** 64", "true"),
+        ("2 ** 64 > 1.5", "true"),
+        ("2 ** 64 + 0.5", "1.8446744073709552e19"),
+        ("let m = #{2 ** 64: \"big\"}; m[18446744073709551616]", "\"big\""),
+        ("\"id {2 ** 64}\"", "\"id 18446744073709551616\""),
+        ("match 2 ** 64 { 18446744073709551616 => 1, _ => 2 }", "1"),
+        ("match -(2 ** 64) { -18446744073709551616 => 1, _ => 2 }", "1"),
+        ("!(2 ** 64)", "false"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("2 ** 64 / 0").contains("Division by zero"));
+    assert!(get_output("2 ** 64 % 0").contains("Division by zero"));
+    assert!(get_output("[1, 2][2 ** 64]").contains("Integer too large"));
+    assert!(get_output("2 ** 64 & 1").contains("Integer too large"));
+    assert!(get_output("\"x\" + 2 ** 100").contains("Expected string but got: '1267650600228229401496703205376'"));
+}
+
+#[test]
+fn test_compound_assignment() {
+    let v = vec![
+        ("let mut x = 1; x += 2; x", "3"),
+        ("let mut x = 10; x -= 2 * 3; x", "4"),
+        ("let mut x = 3; x *= 2 + 1; x", "9"),
+        ("let mut x = 20; x /= 4; x", "5"),
+        ("let mut s = \"a\"; s += \"b\"; s", "\"ab\""),
+        ("let mut f = 1.5; f *= 2; f", "3.0"),
+        // locals are updated in place instead of getting a new slot
+        ("{ let mut total = 0; for i in 1..5 { total += i; } total }", "10"),
+        ("{ let mut n = 0; let m = 7; n = 5; n += 1; n * 10 + m }", "67"),
+        ("{ let mut n = 1; { n = 2; } n }", "2"),
+        ("let mut count = 0; fun inc() { count += 1; } inc(); inc(); count", "2"),
+        ("fun counter() { let mut n = 0; fun next() { n += 1; n } next } let c = counter(); c(); c()", "2"),
+        ("let mut x = 1; x += (let y 2 y * 3); x", "7"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("{ y = 1; }").contains("Cannot assign to undeclared variable 'y'"));
+    assert!(get_output("z += 1;").contains("Cannot assign to undeclared variable 'z'"));
+    assert!(get_output("fun f() { w *= 2; } f();").contains("Cannot assign to undeclared variable 'w'"));
+    assert!(get_output("let k = 1; k += 1;").contains("Cannot assign to immutable variable 'k'"));
+    assert!(get_output("let mut x = \"a\"; x -= 1;").contains("RuntimeError"));
+}
+
+#[test]
+fn test_mutability() {
+    let v = vec![
+        ("let mut x = 1; x = 2; x", "2"),
+        ("let mut (a, b) = (1, 2); a += b; a", "3"),
+        ("{ let mut (a, b) = (1, 2); b *= 10; a + b }", "21"),
+        // redeclaring shadows, and decides whether the new binding is mutable
+        ("let x = 1; let mut x = x; x += 1; x", "2"),
+        ("{ let x = 1; { let mut x = x; x += 5; } x }", "1"),
+        ("let mutable = 2; mutable", "2"),
+    ];
+    test_input_many(&v);
+
+    // locals are checked when compiling
+    let out=get_output("{ let x = 1;\n x = 2; }");
+    assert!(out.contains("(ParseError) [line 2] Error at 'x' - Cannot assign to immutable variable 'x'"));
+    assert!(get_output("fun f(n) { n += 1; n }").contains("Cannot assign to immutable variable 'n'"));
+    assert!(get_output("for i in 0..3 { i = 5; }").contains("Cannot assign to immutable variable 'i'"));
+    assert!(get_output("fun f() { let c = 0; fun g() { c = 1; } g }").contains("Cannot assign to immutable variable 'c'"));
+    assert!(get_output("{ let (a, b) = (1, 2); a = 3; }").contains("Cannot assign to immutable variable 'a'"));
+    assert!(get_output("{ fun f() { 1 } f = 2; }").contains("Cannot assign to immutable variable 'f'"));
+
+    // globals when the assignment runs
+    let out=get_output("let x = 1;\n x = 2;");
+    assert!(out.contains("(RuntimeError) [line 2] Cannot assign to immutable variable 'x': declare it with let mut"));
+    assert!(get_output("let mut x = 1; let x = 2; x = 3;").contains("Cannot assign to immutable variable 'x'"));
+    assert!(get_output("let x = 1; fun set() { x = 2; } set();").contains("Cannot assign to immutable variable 'x'"));
+    assert!(get_output("fun f() { 1 } f = 1;").contains("Cannot assign to immutable variable 'f'"));
+}
+
+#[test]
+fn test_records() {
+    let point="type Point { x, y } let p = Point { x: 1, y: 2 };";
+    let v = vec![
+        ("p", "Point { x: 1, y: 2 }"),
+        ("p.x + p.y", "3"),
+        ("Point { y: 5, x: 4 }", "Point { x: 4, y: 5 }"),
+        ("p with { x: 3 }", "Point { x: 3, y: 2 }"),
+        ("let q = p with { y: 0 }; p", "Point { x: 1, y: 2 }"),
+        ("(p with { x: 10, y: 20 }).y", "20"),
+        ("p == Point { x: 1, y: 2 }", "true"),
+        ("p == p with { x: 2 }", "false"),
+        ("type Other { x, y } p == Other { x: 1, y: 2 }", "false"),
+        ("Point", "<type Point>"),
+        ("fun norm(p) { p.x * p.x + p.y * p.y } norm(p)", "5"),
+        ("[p, Point { x: 3, y: 4 }][1].y", "4"),
+        ("type Line { from, to, } Line { from: p, to: p with { x: 0 } }.to", "Point { x: 0, y: 2 }"),
+        ("let mk = fun (x) { Point { x: x, y: x * 2 } }; mk(3).y", "6"),
+        ("type Empty {} Empty {}", "Empty {}"),
+        ("\"at {p.x},{p.y}\"", "\"at 1,2\""),
+        ("{ type Pair { a, b } let pr = Pair { a: 1, b: (let z 5 z * 2) }; pr.a + pr.b }", "11"),
+        ("fun f() { type Box { v } Box { v: 7 } } f().v", "7"),
+        ("let mut s = 0; for x in [1, 2] { s += x; } s", "3"),
+        ("match p.x { 1 => \"one\", _ => \"other\" }", "\"one\""),
+    ];
+    test_input_many_with_prefix(point, &v);
+
+    assert!(get_output("type Point { x, y } Point { x: 1, z: 2 }").contains("Record Point has no field 'z'"));
+    assert!(get_output("type Point { x, y } Point { x: 1 }").contains("Missing field 'y' for record Point"));
+    assert!(get_output("type Point { x, y }\n let p = Point { x: 1, y: 2 };\n p.z").contains("[line 3] Record Point has no field 'z'"));
+    assert!(get_output("type Point { x, y } Point { x: 1, y: 2 } with { z: 3 }").contains("Record Point has no field 'z'"));
+    assert!(get_output("let n = 1; n.x").contains("Only records have fields but got number"));
+    assert!(get_output("let n = 1; n with { x: 2 }").contains("Only records have fields but got number"));
+    assert!(get_output("let n = 1; n { x: 2 }").contains("Can only make a record of a type but got number"));
+    assert!(get_output("type Point { x, x }").contains("Duplicate field 'x' in type Point"));
+    assert!(get_output("type Point { x, y } Point { x: 1, x: 2 }").contains("Field 'x' given more than once"));
+    assert!(get_output("type Point { x, y } let p = Point { x: 1, y: 2 }; p.x = 3;").contains("Can't assign to field 'x'"));
+}
+
+#[test]
+fn test_sum_types() {
+    let shape="type Shape = Circle(r) | Rect(w, h) | Dot \
+        fun area(s) { match s { Circle(r) => 3 * r * r, Rect(w, h) => w * h, Dot => 0 } }";
+    let v = vec![
+        ("Circle(2)", "Circle(2)"),
+        ("[Circle(2), Rect(3, 4), Dot]", "[Circle(2), Rect(3, 4), Dot]"),
+        ("[area(Circle(2)), area(Rect(3, 4)), area(Dot)]", "[12, 12, 0]"),
+        ("Circle", "<type Circle>"),
+        ("Rect(3, 4).h", "4"),
+        ("Rect(3, 4) with { w: 1 }", "Rect(1, 4)"),
+        ("Rect(1, 2) == Rect(1, 2)", "true"),
+        ("Circle(1) == Rect(1, 1)", "false"),
+        ("Dot == Dot", "true"),
+        ("let make = Circle; make(5)", "Circle(5)"),
+        ("fun apply(f, x) { f(x) } Circle >> apply(3)", "Circle(3)"),
+        ("match Rect(1, 2) { Rect(w, h) if w > h => \"wide\", Rect(_, _) => \"tall\", _ => \"other\" }", "\"tall\""),
+        ("match Circle(1) { Rect(w, h) => w * h, _ => -1 }", "-1"),
+        ("match 5 { Dot => 0, n => n }", "5"),
+        ("match (Dot, Circle(3)) { (Dot, Circle(r)) => r, _ => 0 }", "3"),
+        ("\"{Rect(1, 2)}\"", "\"Rect(1, 2)\""),
+        ("{ type Light = Red | Green; match Green { Red => \"stop\", Green => \"go\" } }", "\"go\""),
+        ("type Expr = Num(n) | Add(l, r) | Mul(l, r); \
+          fun eval(e) { match e { Num(n) => n, Add(l, r) => eval(l) + eval(r), Mul(l, r) => eval(l) * eval(r) } } \
+          eval(Add(Num(1), Mul(Num(2), Num(3))))", "7"),
+        ("type Expr = Num(n) | Add(l, r); match Add(Num(1), Num(2)) { Add(Num(1), x) => x, _ => Num(0) }", "Num(2)"),
+        // records can be matched by position too
+        ("type Point { x, y } match Point { x: 1, y: 2 } { Point(x, y) => x + y }", "3"),
+        // capitalised names that aren't declared types bind like any other name
+        ("match 5 { N => N }", "5"),
+        ("match (1, 2) { (A, B) => A + B }", "3"),
+        ("let X = 3; match 3 { X => X + 1 }", "4"),
+        ("match [Dot, 7] { [Dot, Seven] => Seven, _ => 0 }", "7"),
+    ];
+    test_input_many_with_prefix(shape, &v);
+
+    let shape="type Shape = Circle(r) | Rect(w, h) | Dot";
+    assert!(get_output(&format!("{} Rect(1)", shape)).contains("Expected 2 arguments but got 1"));
+    assert!(get_output(&format!("{} Dot()", shape)).contains("Can only call functions but got record"));
+    assert!(get_output(&format!("{} match Rect(1, 2) {{ Rect(w) => w }}", shape)).contains("Expected 2 fields in pattern for Rect but got 1"));
+    assert!(get_output(&format!("{} match Dot {{ Circle(r) => r }}", shape)).contains("No match arm matched Dot"));
+    assert!(get_output("type Thing { a } let Thing = 1; match 1 { Thing => 1 }").contains("Can only match a constructor pattern against a type but got number"));
+    assert!(get_output("type Shape = Circle(r) | Circle(d)").contains("Duplicate variant 'Circle' in type Shape"));
+    assert!(get_output("type Shape = Rect(w, w)").contains("Duplicate field 'w' in type Rect"));
+}
+
+#[test]
+fn test_imports() {
+    let v = vec![
+        ("import \"tests/modules/utils\"; utils.double(4)", "8"),
+        ("import \"tests/modules/utils\" as u; u.name", "\"utils\""),
+        ("import \"tests/modules/utils\" as u; u", "<module utils>"),
+        // the module's globals are its own
+        ("let scale = 10; import \"tests/modules/utils\" as u; u.double(4) + scale", "18"),
+        ("let name = \"main\"; import \"tests/modules/utils\" as u; name", "\"main\""),
+        // runs once: both names refer to the same module
+        ("import \"tests/modules/utils\" as a; import \"tests/modules/utils\" as b; a.bump(); b.bump(); a.count", "2"),
+        ("import \"tests/modules/utils\" as u; let Circle = u.Circle; match u.Circle(2) { Circle(r) => r }", "2"),
+        ("import \"tests/modules/utils\" as u; u.Dot", "Dot"),
+        ("import \"tests/modules/outer\"; outer.value", "42"),
+        ("fun f() { import \"tests/modules/lib/inner\" as i; i.value } f()", "41"),
+    ];
+    test_input_many(&v);
+
+    assert!(get_output("import \"tests/modules/utils\" as u; u.scale").contains("Module utils does not export 'scale'"));
+    assert!(get_output("import \"tests/modules/utils\" as u; u.count = 1").contains("Can't assign to field 'count'"));
+    assert!(get_output("import \"tests/modules/missing\"").contains("Module 'tests/modules/missing' not found"));
+    assert!(get_output("import \"tests/modules/cycle_a\"").contains("Import cycle: cycle_a -> cycle_b -> cycle_a"));
+    assert!(get_output("import \"tests/modules/broken\"").contains("Error in module broken"));
+    assert!(get_output("import \"tests/my-mod\"").contains("Module name 'my-mod' is not an identifier: import it with as"));
+    assert!(get_output("fun f() { export let x = 1; }").contains("Can only export declarations at the top level"));
+    assert!(get_output("export 1 + 2").contains("Expected a let, fun or type declaration after export"));
+
+    output_has("imports", "\"loading utils\"\n42\n2\ntrue\n");
+}
+
+#[test]
+fn test_garbage_collection() {
+    let garbage="fun garbage() { let mut i = 0; while (i < 5000) { let xs = [i, (i, i)]; let f = fun () { xs }; let r = 0..i; i += 1; } }";
+    let v = vec![
+        // objects made in each iteration are freed, the ones still reachable are kept
+        ("let keep = [1, [2, 3]]; garbage(); keep", "[1, [2, 3]]"),
+        ("let m = #{\"a\": [3], \"b\": (4, 5)}; garbage(); m", "#{\"a\": [3], \"b\": (4, 5)}"),
+        ("fun counter() { let mut n = 0; fun () { n += 1; n } } let next = counter(); next(); garbage(); next()", "2"),
+        ("let big = 2 ** 100; garbage(); (big + 1, big == 2 ** 100)", "(1267650600228229401496703205377, true)"),
+        ("fun f() { let local = [1, 2]; garbage(); local } f()", "[1, 2]"),
+    ];
+    test_input_many_with_prefix(garbage, &v);
+
+    let mut vm=VM::new();
+    vm.interpret("let mut i = 0; while (i < 100000) { let xs = [i]; let f = fun () { xs }; i += 1; }").unwrap();
+    assert!(vm.live_objects() < 5000);
+}